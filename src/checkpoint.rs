@@ -0,0 +1,97 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Records how many leading chunks have been inserted, so that an interrupted load can be resumed
+//
+// Chunks are inserted concurrently, so the checkpoint only advances past a chunk once every chunk
+// before it has also completed. The file holds the next chunk index and the chunk size, e.g. "42 1000"
+pub struct Checkpoint {
+    path: PathBuf,
+    chunk_size: usize,
+    state: Mutex<CheckpointState>,
+}
+
+struct CheckpointState {
+    next_chunk: u64,
+    completed: BTreeSet<u64>,
+}
+
+// The contents of an existing checkpoint file
+pub struct SavedCheckpoint {
+    pub next_chunk: u64,
+    pub chunk_size: usize,
+}
+
+impl SavedCheckpoint {
+    // The number of records covered by the completed chunks
+    pub fn records(&self) -> u64 {
+        self.next_chunk * self.chunk_size as u64
+    }
+}
+
+impl Checkpoint {
+    pub fn new(path: &Path, chunk_size: usize, next_chunk: u64) -> Self {
+        Checkpoint {
+            path: path.to_path_buf(),
+            chunk_size,
+            state: Mutex::new(CheckpointState {
+                next_chunk,
+                completed: BTreeSet::new(),
+            }),
+        }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Option<SavedCheckpoint>> {
+        // No file means there is nothing to resume
+        let contents: String = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a valid checkpoint file", path.display()),
+            )
+        };
+
+        // Parse the next chunk index and the chunk size
+        let mut values = contents.split_whitespace();
+        let next_chunk: u64 = values.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+        let chunk_size: usize = values.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+
+        Ok(Some(SavedCheckpoint {
+            next_chunk,
+            chunk_size,
+        }))
+    }
+
+    pub fn complete(&self, chunk: u64) -> std::io::Result<()> {
+        let mut guard = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let state: &mut CheckpointState = &mut guard;
+
+        state.completed.insert(chunk);
+
+        // Advance over every contiguous completed chunk
+        let start: u64 = state.next_chunk;
+
+        while state.completed.remove(&state.next_chunk) {
+            state.next_chunk += 1;
+        }
+
+        // Only write the file if the checkpoint moved
+        match state.next_chunk > start {
+            true => std::fs::write(&self.path, format!("{} {}\n", state.next_chunk, self.chunk_size)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn remove(&self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
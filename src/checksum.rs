@@ -0,0 +1,248 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use clap::ValueEnum;
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+// The digest used for --expected-checksum, mirrors publish different ones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "SHA-256",
+            ChecksumAlgorithm::Sha1 => "SHA-1",
+            ChecksumAlgorithm::Md5 => "MD5",
+            ChecksumAlgorithm::Blake3 => "BLAKE3",
+        }
+    }
+
+    // The length of the digest written as hex
+    pub fn hex_length(&self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha256 | ChecksumAlgorithm::Blake3 => 64,
+            ChecksumAlgorithm::Sha1 => 40,
+            ChecksumAlgorithm::Md5 => 32,
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+// A running digest of one of the supported algorithms
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+    Md5(Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha1(hasher) => hasher.update(bytes),
+            Hasher::Md5(hasher) => hasher.update(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    // The digest as lowercase hex
+    pub fn finish(self) -> String {
+        let digest: Vec<u8> = match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        };
+
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+// Wraps the source so every byte read by the CSV reader is also added to the digest
+pub struct ChecksumReader<R> {
+    inner: R,
+    hasher: Option<Arc<Mutex<Option<Hasher>>>>,
+}
+
+// The digest of a ChecksumReader, read once the source has ended
+pub struct ChecksumHandle {
+    hasher: Arc<Mutex<Option<Hasher>>>,
+}
+
+impl<R> ChecksumReader<R> {
+    // Wrap a reader, hashing its bytes only if an algorithm is given
+    pub fn new(inner: R, algorithm: Option<ChecksumAlgorithm>) -> (Self, Option<ChecksumHandle>) {
+        let hasher: Option<Arc<Mutex<Option<Hasher>>>> = algorithm.map(|algorithm| Arc::new(Mutex::new(Some(algorithm.hasher()))));
+        let handle: Option<ChecksumHandle> = hasher.clone().map(|hasher| ChecksumHandle { hasher });
+
+        (ChecksumReader { inner, hasher }, handle)
+    }
+}
+
+impl ChecksumHandle {
+    // The digest of everything read so far, None if it has already been taken
+    pub fn finish(&self) -> Option<String> {
+        let mut hasher = self.hasher.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        hasher.take().map(Hasher::finish)
+    }
+}
+
+impl<R> AsyncRead for ChecksumReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled: usize = buf.filled().len();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        // Hash whatever this read added to the buffer
+        if let (Poll::Ready(Ok(())), Some(hasher)) = (&poll, &this.hasher) {
+            let mut hasher = hasher.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&buf.filled()[filled..]);
+            }
+        }
+
+        poll
+    }
+}
+
+// Check an expected checksum is hex of the right length for the algorithm
+pub fn validate_checksum(expected: &str, algorithm: ChecksumAlgorithm) -> Result<(), String> {
+    match expected.len() == algorithm.hex_length() && expected.chars().all(|character| character.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(format!(
+            "--expected-checksum should be {} hex digits for {}, got {}",
+            algorithm.hex_length(),
+            algorithm.name(),
+            expected
+        )),
+    }
+}
+
+// The expected digest from a sidecar file, either just the digest or sha256sum style lines of
+// "digest  file name", taking the line for the downloaded file when the sidecar lists several
+pub fn parse_sidecar(contents: &str, file_name: Option<&str>, algorithm: ChecksumAlgorithm) -> Result<String, String> {
+    // The file name may have a path, or a * before it for a digest of a file read in binary mode
+    let entries: Vec<(&str, Option<&str>)> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(char::is_whitespace) {
+            Some((digest, name)) => (digest, Path::new(name.trim_start().trim_start_matches('*')).file_name().and_then(|name| name.to_str())),
+            None => (line, None),
+        })
+        .collect();
+
+    let digest: &str = match entries.as_slice() {
+        [] => return Err("the checksum file is empty".to_string()),
+        [(digest, _)] => digest,
+        _ => match entries.iter().find(|(_, name)| name.is_some() && *name == file_name) {
+            Some((digest, _)) => digest,
+            None => return Err(format!("the checksum file has no line for {}", file_name.unwrap_or("the download"))),
+        },
+    };
+
+    match validate_checksum(digest, algorithm) {
+        Ok(_) => Ok(digest.to_ascii_lowercase()),
+        Err(_) => Err(format!("the checksum file holds {}, which is not a {} digest", digest, algorithm.name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    // Published digests of "abc"
+    const ABC_DIGESTS: [(ChecksumAlgorithm, &str); 4] = [
+        (ChecksumAlgorithm::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        (ChecksumAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d"),
+        (ChecksumAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+        (ChecksumAlgorithm::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+    ];
+
+    #[test]
+    fn each_algorithm_matches_its_known_digest() {
+        for (algorithm, expected) in ABC_DIGESTS {
+            let mut hasher: Hasher = algorithm.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+
+            assert_eq!(hasher.finish(), expected, "{}", algorithm.name());
+            assert_eq!(expected.len(), algorithm.hex_length());
+        }
+    }
+
+    #[tokio::test]
+    async fn the_reader_hashes_everything_read() {
+        for (algorithm, expected) in ABC_DIGESTS {
+            let (mut reader, handle) = ChecksumReader::new(Cursor::new(b"abc".to_vec()), Some(algorithm));
+
+            let mut bytes: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut bytes).await.unwrap();
+
+            let handle: ChecksumHandle = handle.unwrap();
+            assert_eq!(bytes, b"abc");
+            assert_eq!(handle.finish().as_deref(), Some(expected));
+            assert_eq!(handle.finish(), None);
+        }
+    }
+
+    #[test]
+    fn expected_checksums_are_checked_for_length_and_digits() {
+        assert!(validate_checksum("900150983cd24fb0d6963f7d28e17f72", ChecksumAlgorithm::Md5).is_ok());
+        assert!(validate_checksum("900150983CD24FB0D6963F7D28E17F72", ChecksumAlgorithm::Md5).is_ok());
+
+        assert!(validate_checksum("900150983cd24fb0d6963f7d28e17f72", ChecksumAlgorithm::Sha256).is_err());
+        assert!(validate_checksum("z00150983cd24fb0d6963f7d28e17f72", ChecksumAlgorithm::Md5).is_err());
+    }
+
+    #[test]
+    fn sidecars_give_the_digest_for_the_download() {
+        const MD5: ChecksumAlgorithm = ChecksumAlgorithm::Md5;
+        let digest: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+        assert_eq!(parse_sidecar("900150983CD24FB0D6963F7D28E17F72\n", None, MD5).unwrap(), digest);
+        assert_eq!(parse_sidecar(&format!("{}  aircraftDatabase.csv\n", digest), None, MD5).unwrap(), digest);
+
+        // With several lines the one for the downloaded file is used
+        let sums: String = format!("{}  other.csv\n{} *./aircraftDatabase.csv\n", "0".repeat(32), digest);
+        assert_eq!(parse_sidecar(&sums, Some("aircraftDatabase.csv"), MD5).unwrap(), digest);
+        assert!(parse_sidecar(&sums, Some("missing.csv"), MD5).is_err());
+
+        assert!(parse_sidecar("\n", None, MD5).is_err());
+        assert!(parse_sidecar("<html>Not Found</html>", None, MD5).is_err());
+    }
+}
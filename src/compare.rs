@@ -0,0 +1,283 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use bson::{Bson, Document};
+
+use colored::Colorize;
+
+use futures::stream::{Stream, StreamExt};
+
+use serde::Serialize;
+
+use crate::config::DEFAULT_MONGO_PORT;
+use crate::summary::SummaryFormat;
+
+// The number of keys kept as examples of each kind of difference
+const DIFF_EXAMPLES: usize = 5;
+
+// A collection to compare, written as [mongodb://]host[:port]/database.collection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollectionTarget {
+    pub host: String,
+    pub port: u16,
+    pub database_name: String,
+    pub collection_name: String,
+}
+
+impl FromStr for CollectionTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || format!("expected [mongodb://]host[:port]/database.collection, got {}", value);
+
+        let (address, namespace) = value.strip_prefix("mongodb://").unwrap_or(value).split_once('/').ok_or_else(error)?;
+
+        // Database names can't contain a dot, so the first one ends it
+        let (database_name, collection_name) = namespace.split_once('.').ok_or_else(error)?;
+
+        let (host, port) = match address.split_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| error())?),
+            None => (address, DEFAULT_MONGO_PORT),
+        };
+
+        if host.is_empty() || database_name.is_empty() || collection_name.is_empty() {
+            return Err(error());
+        }
+
+        Ok(CollectionTarget {
+            host: host.to_string(),
+            port,
+            database_name: database_name.to_string(),
+            collection_name: collection_name.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for CollectionTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}/{}.{}", self.host, self.port, self.database_name, self.collection_name)
+    }
+}
+
+// The differences between two collections matched on a key field
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionDiff {
+    pub key: String,
+    pub only_left: u64,
+    pub only_right: u64,
+    pub identical: u64,
+    pub different: u64,
+    // The number of matched documents in which each field differs
+    pub fields: BTreeMap<String, u64>,
+    pub only_left_examples: Vec<String>,
+    pub only_right_examples: Vec<String>,
+    pub different_examples: Vec<String>,
+}
+
+impl CollectionDiff {
+    pub fn new(key: &str) -> Self {
+        CollectionDiff {
+            key: key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Whether the collections hold the same documents
+    pub fn is_empty(&self) -> bool {
+        self.only_left == 0 && self.only_right == 0 && self.different == 0
+    }
+
+    fn add_only_left(&mut self, key: String) {
+        self.only_left += 1;
+        add_example(&mut self.only_left_examples, key);
+    }
+
+    fn add_only_right(&mut self, key: String) {
+        self.only_right += 1;
+        add_example(&mut self.only_right_examples, key);
+    }
+
+    // Compare two documents with the same key field by field, the _id of each is its own
+    fn add_pair(&mut self, key: String, left: &Document, right: &Document) {
+        let fields: BTreeSet<&String> = left.keys().chain(right.keys()).filter(|field| *field != "_id").collect();
+
+        let mut different: bool = false;
+
+        for field in fields {
+            if left.get(field) != right.get(field) {
+                *self.fields.entry(field.clone()).or_default() += 1;
+                different = true;
+            }
+        }
+
+        match different {
+            true => {
+                self.different += 1;
+                add_example(&mut self.different_examples, key);
+            }
+            false => self.identical += 1,
+        }
+    }
+
+    pub fn print(&self, left: &CollectionTarget, right: &CollectionTarget, format: SummaryFormat) {
+        if format == SummaryFormat::Json {
+            match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(error) => {
+                    let text = format!("Warning: could not write the comparison as JSON: {}", error);
+                    crate::report::warning(&text);
+                }
+            }
+
+            return;
+        }
+
+        let text: String = format!("Compared {} with {} on {}", left, right, self.key);
+        println!("{}", text.yellow().bold());
+
+        let lines: [(String, &Vec<String>); 3] = [
+            (format!("{} documents only in {}", self.only_left, left), &self.only_left_examples),
+            (format!("{} documents only in {}", self.only_right, right), &self.only_right_examples),
+            (format!("{} matched documents differ, {} are identical", self.different, self.identical), &self.different_examples),
+        ];
+
+        for (text, examples) in lines {
+            match examples.is_empty() {
+                true => println!("{}", text.blue().bold()),
+                false => println!("{}", format!("{}, e.g. {}", text, examples.join(", ")).blue().bold()),
+            }
+        }
+
+        for (field, count) in &self.fields {
+            println!("  {}: differs in {} documents", field, count);
+        }
+    }
+}
+
+fn add_example(examples: &mut Vec<String>, key: String) {
+    if examples.len() < DIFF_EXAMPLES {
+        examples.push(key);
+    }
+}
+
+// The key of a document as a string, empty if it has none
+fn key_of(document: &Document, key: &str) -> String {
+    match document.get(key) {
+        Some(Bson::String(value)) => value.clone(),
+        Some(Bson::Null) | None => String::new(),
+        Some(value) => value.to_string(),
+    }
+}
+
+// Merge two streams of documents sorted on the key, holding only one document from each at a time
+//
+// Documents that share a key within a collection are paired in the order they arrive, and any
+// left over are counted as only in that collection
+pub async fn compare_sorted<L, R, E>(key: &str, left: L, right: R) -> Result<CollectionDiff, E>
+where
+    L: Stream<Item = Result<Document, E>> + Unpin,
+    R: Stream<Item = Result<Document, E>> + Unpin,
+{
+    let mut left = left;
+    let mut right = right;
+    let mut diff: CollectionDiff = CollectionDiff::new(key);
+
+    let mut left_document: Option<Document> = left.next().await.transpose()?;
+    let mut right_document: Option<Document> = right.next().await.transpose()?;
+
+    loop {
+        match (&left_document, &right_document) {
+            (None, None) => break,
+            (Some(document), None) => {
+                diff.add_only_left(key_of(document, key));
+                left_document = left.next().await.transpose()?;
+            }
+            (None, Some(document)) => {
+                diff.add_only_right(key_of(document, key));
+                right_document = right.next().await.transpose()?;
+            }
+            (Some(left_value), Some(right_value)) => {
+                let (left_key, right_key) = (key_of(left_value, key), key_of(right_value, key));
+
+                match left_key.cmp(&right_key) {
+                    Ordering::Less => {
+                        diff.add_only_left(left_key);
+                        left_document = left.next().await.transpose()?;
+                    }
+                    Ordering::Greater => {
+                        diff.add_only_right(right_key);
+                        right_document = right.next().await.transpose()?;
+                    }
+                    Ordering::Equal => {
+                        diff.add_pair(left_key, left_value, right_value);
+                        left_document = left.next().await.transpose()?;
+                        right_document = right.next().await.transpose()?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn targets_are_parsed_with_a_default_port() {
+        let target: CollectionTarget = "mongodb://staging:27018/web_database.aircraft.v2".parse().unwrap();
+        assert_eq!(target.host, "staging");
+        assert_eq!(target.port, 27018);
+        assert_eq!(target.database_name, "web_database");
+        assert_eq!(target.collection_name, "aircraft.v2");
+
+        let target: CollectionTarget = "macmini2/web_database.aircraft_collection".parse().unwrap();
+        assert_eq!(target.port, DEFAULT_MONGO_PORT);
+        assert_eq!(target.to_string(), "macmini2:27017/web_database.aircraft_collection");
+
+        assert!("macmini2/web_database".parse::<CollectionTarget>().is_err());
+        assert!("macmini2:port/web_database.aircraft".parse::<CollectionTarget>().is_err());
+        assert!("/web_database.aircraft".parse::<CollectionTarget>().is_err());
+    }
+
+    #[tokio::test]
+    async fn sorted_collections_are_merged_on_the_key() {
+        let left: Vec<Result<Document, ()>> = vec![
+            Ok(doc! { "_id": 1, "icao24": "A1", "model": "A320", "owner": "Aer Lingus" }),
+            Ok(doc! { "_id": 2, "icao24": "B2", "model": "B737" }),
+            Ok(doc! { "_id": 3, "icao24": "C3", "model": "A330" }),
+        ];
+
+        let right: Vec<Result<Document, ()>> = vec![
+            Ok(doc! { "_id": 7, "icao24": "A1", "model": "A320", "owner": "Ryanair" }),
+            Ok(doc! { "_id": 8, "icao24": "B2", "model": "B737" }),
+            Ok(doc! { "_id": 9, "icao24": "B3", "model": "B747", "country": "Ireland" }),
+            Ok(doc! { "_id": 10, "icao24": "D4" }),
+        ];
+
+        let diff: CollectionDiff = compare_sorted("icao24", stream::iter(left), stream::iter(right)).await.unwrap();
+
+        assert_eq!((diff.only_left, diff.only_right, diff.identical, diff.different), (1, 2, 1, 1));
+        assert_eq!(diff.only_left_examples, ["C3"]);
+        assert_eq!(diff.only_right_examples, ["B3", "D4"]);
+        assert_eq!(diff.different_examples, ["A1"]);
+        assert_eq!(diff.fields, BTreeMap::from([("owner".to_string(), 1)]));
+        assert!(!diff.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_errors_stop_the_comparison() {
+        let left: Vec<Result<Document, &str>> = vec![Ok(doc! { "icao24": "A1" }), Err("cursor failed")];
+        let right: Vec<Result<Document, &str>> = vec![Ok(doc! { "icao24": "A1" }), Ok(doc! { "icao24": "B2" })];
+
+        let result = compare_sorted("icao24", stream::iter(left), stream::iter(right)).await;
+        assert!(matches!(result, Err("cursor failed")));
+    }
+}
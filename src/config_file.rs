@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+// Errors that can occur when loading the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    TomlError(toml::de::Error),
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::IoError(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::TomlError(error)
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::IoError(error) => write!(f, "Config file error: {}", error),
+            ConfigError::TomlError(error) => write!(f, "Config file error: {}", error),
+        }
+    }
+}
+
+// The TOML config file, for example
+//
+// [columns]
+// manufacturer = "manufacturerName"
+//
+// maps the CSV column "manufacturer" onto the model field "manufacturerName", and
+//
+// [output_fields]
+// manufacturerName = "manufacturer"
+//
+// stores the model field "manufacturerName" as "manufacturer", and
+//
+// [[datasets]]
+// url = "https://example.com/aircraftDatabase.csv"
+// collection = "aircraft"
+//
+// loads each listed file into its collection in turn, in place of --url and --collection-name
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+    #[serde(default)]
+    pub output_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub datasets: Vec<DatasetEntry>,
+}
+
+// One file loaded by a multi-dataset run, its columns are renamed on top of the shared [columns]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetEntry {
+    pub name: Option<String>,
+    pub url: String,
+    pub collection: String,
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+}
+
+impl DatasetEntry {
+    // The name in the reports, the collection unless one is given
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.collection)
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents: String = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datasets_are_read_in_order() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+            [columns]
+            manufacturer = "manufacturerName"
+
+            [[datasets]]
+            url = "https://example.com/aircraftDatabase.csv"
+            collection = "aircraft"
+
+            [[datasets]]
+            name = "mirror"
+            url = "/data/aircraft.csv"
+            collection = "aircraft_mirror"
+            columns = { reg = "registration" }
+            "#,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = config_file.datasets.iter().map(DatasetEntry::name).collect();
+        assert_eq!(names, ["aircraft", "mirror"]);
+        assert_eq!(config_file.datasets[1].columns["reg"], "registration");
+    }
+}
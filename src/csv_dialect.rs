@@ -0,0 +1,294 @@
+use clap::ValueEnum;
+
+use csv_async::{AsyncReaderBuilder, Terminator};
+
+// The OpenSky files are comma separated and quote fields with single quotes
+const DEFAULT_DELIMITER: u8 = b',';
+const DEFAULT_QUOTE: u8 = b'\'';
+// Most other CSV, e.g. a mirror re-exported by a spreadsheet or database, quotes fields with double quotes
+const STANDARD_QUOTE: u8 = b'"';
+
+// The layout of a dataset, selected with --dataset, which the --csv-* flags then adjust, airports
+// also read the records as the airport model
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DatasetProfile {
+    /// The OpenSky aircraft database, with fields in single quotes
+    #[default]
+    Aircraft,
+
+    /// Standard RFC 4180 CSV, with fields in double quotes
+    #[value(alias = "standard")]
+    Rfc4180,
+
+    /// Airport metadata such as the OurAirports airports.csv, with fields in double quotes
+    Airports,
+}
+
+impl DatasetProfile {
+    pub fn csv_dialect(&self) -> CsvDialect {
+        match self {
+            DatasetProfile::Aircraft => CsvDialect::default(),
+            DatasetProfile::Rfc4180 | DatasetProfile::Airports => CsvDialect {
+                quote: STANDARD_QUOTE,
+                ..CsvDialect::default()
+            },
+        }
+    }
+}
+
+// How the CSV is laid out, the defaults match the OpenSky files
+#[derive(Clone, Copy)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub terminator: Terminator,
+    pub escape: Option<u8>,
+    pub double_quote: bool,
+    // Whether the quote character encloses fields, or is read as an ordinary character
+    pub quoting: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: DEFAULT_DELIMITER,
+            quote: DEFAULT_QUOTE,
+            terminator: Terminator::CRLF,
+            escape: None,
+            double_quote: true,
+            quoting: true,
+        }
+    }
+}
+
+impl CsvDialect {
+    // Check that the special bytes can be told apart
+    pub fn validate(&self) -> Result<(), String> {
+        if self.delimiter == self.quote {
+            return Err("the CSV delimiter and quote must differ".to_string());
+        }
+
+        if let Terminator::Any(terminator) = self.terminator {
+            if terminator == self.delimiter || terminator == self.quote {
+                return Err("the CSV terminator must differ from the delimiter and quote".to_string());
+            }
+        }
+
+        if let Some(escape) = self.escape {
+            if escape == self.delimiter || escape == self.quote {
+                return Err("the CSV escape must differ from the delimiter and quote".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn builder(&self) -> AsyncReaderBuilder {
+        let mut builder: AsyncReaderBuilder = AsyncReaderBuilder::new();
+
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .terminator(self.terminator)
+            .escape(self.escape)
+            .double_quote(self.double_quote)
+            .quoting(self.quoting)
+            // Records may have more fields than the header, the extra trailing fields are dropped
+            .flexible(true);
+
+        builder
+    }
+
+    // The same layout with the quotes read as ordinary characters, for a record whose quotes don't pair up
+    pub fn relaxed(&self) -> CsvDialect {
+        CsvDialect {
+            quoting: false,
+            ..*self
+        }
+    }
+
+    // Strip the quotes around a field read by the relaxed dialect, either of which may be the one
+    // missing, collapsing doubled quotes inside it
+    pub fn unquote(&self, field: &str) -> String {
+        let quote: char = self.quote as char;
+
+        let inner: &str = field.strip_prefix(quote).unwrap_or(field);
+        let inner: &str = inner.strip_suffix(quote).unwrap_or(inner);
+
+        match inner.len() < field.len() && self.double_quote {
+            true => inner.replace(&format!("{}{}", quote, quote), &quote.to_string()),
+            false => inner.to_string(),
+        }
+    }
+}
+
+// Parse a single ASCII byte, allowing the escapes \t, \n, \r and \\
+pub fn parse_csv_byte(value: &str) -> Result<u8, String> {
+    let byte: u8 = match value {
+        "\\t" => b'\t',
+        "\\n" => b'\n',
+        "\\r" => b'\r',
+        "\\\\" => b'\\',
+        _ => match value.as_bytes() {
+            [byte] if byte.is_ascii() => *byte,
+            _ => return Err(format!("expected a single ASCII character, got {:?}", value)),
+        },
+    };
+
+    Ok(byte)
+}
+
+// Parse a record terminator, "crlf" accepts any of \r\n, \r and \n, otherwise a single byte
+pub fn parse_terminator(value: &str) -> Result<Terminator, String> {
+    match value.eq_ignore_ascii_case("crlf") {
+        true => Ok(Terminator::CRLF),
+        false => Ok(Terminator::Any(parse_csv_byte(value)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    async fn read(csv_dialect: CsvDialect, csv: &str) -> Vec<Vec<String>> {
+        let mut reader = csv_dialect
+            .builder()
+            .has_headers(false)
+            .create_reader(Cursor::new(csv.as_bytes().to_vec()));
+
+        reader
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn reads_crlf_terminated_records() {
+        let records = read(CsvDialect::default(), "'a','b'\r\n'c','d'\r\n'e','f'\n").await;
+
+        assert_eq!(records, [["a", "b"], ["c", "d"], ["e", "f"]]);
+    }
+
+    #[tokio::test]
+    async fn reads_a_single_byte_terminator() {
+        let csv_dialect: CsvDialect = CsvDialect {
+            terminator: parse_terminator(";").unwrap(),
+            ..CsvDialect::default()
+        };
+
+        let records = read(csv_dialect, "'a','b';'c','d';").await;
+
+        assert_eq!(records, [["a", "b"], ["c", "d"]]);
+    }
+
+    #[tokio::test]
+    async fn reads_a_semicolon_delimited_export() {
+        let csv_dialect: CsvDialect = CsvDialect {
+            delimiter: parse_csv_byte(";").unwrap(),
+            quote: parse_csv_byte("\"").unwrap(),
+            ..CsvDialect::default()
+        };
+
+        assert!(csv_dialect.validate().is_ok());
+
+        let records = read(csv_dialect, "\"4ca1fa\";\"Airbus; SAS\";'A320'\r\n").await;
+        assert_eq!(records, [["4ca1fa", "Airbus; SAS", "'A320'"]]);
+
+        // A field couldn't be told apart from a quoted one
+        let csv_dialect: CsvDialect = CsvDialect {
+            delimiter: b'\'',
+            ..CsvDialect::default()
+        };
+        assert_eq!(csv_dialect.validate().unwrap_err(), "the CSV delimiter and quote must differ");
+    }
+
+    #[tokio::test]
+    async fn reads_backslash_escaped_quotes() {
+        let csv_dialect: CsvDialect = CsvDialect {
+            escape: Some(b'\\'),
+            double_quote: false,
+            ..CsvDialect::default()
+        };
+
+        let records = read(csv_dialect, "'O\\'Hare','b'\r\n").await;
+
+        assert_eq!(records, [["O'Hare", "b"]]);
+    }
+
+    #[tokio::test]
+    async fn the_aircraft_profile_reads_single_quotes() {
+        let csv_dialect: CsvDialect = DatasetProfile::Aircraft.csv_dialect();
+        let records = read(csv_dialect, "'4ca1fa','Boeing, Inc','say \"hi\"'\r\n").await;
+
+        assert_eq!(records, [["4ca1fa", "Boeing, Inc", "say \"hi\""]]);
+    }
+
+    #[tokio::test]
+    async fn the_rfc4180_profile_reads_double_quotes() {
+        let csv_dialect: CsvDialect = DatasetProfile::Rfc4180.csv_dialect();
+        let records = read(csv_dialect, "\"4ca1fa\",\"Boeing, Inc\",\"O'Hare \"\"North\"\"\"\r\n").await;
+
+        assert_eq!(records, [["4ca1fa", "Boeing, Inc", "O'Hare \"North\""]]);
+
+        // The single quote profile would split the quoted comma
+        let records = read(DatasetProfile::Aircraft.csv_dialect(), "\"4ca1fa\",\"Boeing, Inc\"\r\n").await;
+        assert_eq!(records[0].len(), 3);
+    }
+
+    #[tokio::test]
+    async fn the_relaxed_dialect_reads_unpaired_quotes() {
+        let csv_dialect: CsvDialect = CsvDialect::default();
+        let records = read(csv_dialect.relaxed(), "'4ca1fa','O'Hare','it''s'\r\n").await;
+
+        let fields: Vec<String> = records[0].iter().map(|field| csv_dialect.unquote(field)).collect();
+        assert_eq!(fields, ["4ca1fa", "O'Hare", "it's"]);
+
+        // A field missing one of its quotes loses the other, unquoted fields are kept as they are
+        assert_eq!(csv_dialect.unquote("'4ca1fa"), "4ca1fa");
+        assert_eq!(csv_dialect.unquote("A320'"), "A320");
+        assert_eq!(csv_dialect.unquote("A320"), "A320");
+    }
+
+    #[test]
+    fn parses_bytes() {
+        assert_eq!(parse_csv_byte(";"), Ok(b';'));
+        assert_eq!(parse_csv_byte("\\t"), Ok(b'\t'));
+        assert_eq!(parse_csv_byte("\\n"), Ok(b'\n'));
+        assert_eq!(parse_csv_byte("\\r"), Ok(b'\r'));
+        assert_eq!(parse_csv_byte("\\\\"), Ok(b'\\'));
+
+        assert!(parse_csv_byte("").is_err());
+        assert!(parse_csv_byte(";;").is_err());
+        assert!(parse_csv_byte("é").is_err());
+    }
+
+    #[test]
+    fn parses_terminators() {
+        assert!(matches!(parse_terminator("crlf"), Ok(Terminator::CRLF)));
+        assert!(matches!(parse_terminator("CRLF"), Ok(Terminator::CRLF)));
+        assert!(matches!(parse_terminator("\\n"), Ok(Terminator::Any(b'\n'))));
+        assert!(parse_terminator("lf").is_err());
+    }
+
+    #[test]
+    fn rejects_clashing_bytes() {
+        let csv_dialect: CsvDialect = CsvDialect {
+            terminator: Terminator::Any(b','),
+            ..CsvDialect::default()
+        };
+        assert!(csv_dialect.validate().is_err());
+
+        let csv_dialect: CsvDialect = CsvDialect {
+            escape: Some(b'\''),
+            ..CsvDialect::default()
+        };
+        assert!(csv_dialect.validate().is_err());
+
+        assert!(CsvDialect::default().validate().is_ok());
+    }
+}
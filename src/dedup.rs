@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use bson::{Bson, Document};
+
+use serde::Serialize;
+
+// Separates the key values before hashing, so ("ab", "c") and ("a", "bc") differ
+const KEY_SEPARATOR: char = '\u{1f}';
+
+// Drops records whose key fields match an earlier record, keeping the first
+//
+// Only a hash of each key is kept, so memory stays small on the full dataset
+pub struct Deduplicator {
+    fields: Vec<String>,
+    seen: HashSet<u64>,
+}
+
+impl Deduplicator {
+    pub fn new(fields: Vec<String>) -> Self {
+        Deduplicator {
+            fields,
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn is_duplicate<T>(&mut self, record: &T) -> bool
+    where
+        T: Serialize,
+    {
+        // Records that can't be converted are never treated as duplicates
+        let document: Document = match bson::to_document(record) {
+            Ok(document) => document,
+            Err(_) => return false,
+        };
+
+        // Hash the key values joined with the separator
+        let mut key: String = String::new();
+
+        for field in &self.fields {
+            match document.get(field) {
+                Some(Bson::String(value)) => key.push_str(value),
+                Some(value) => key.push_str(&value.to_string()),
+                None => {}
+            }
+
+            key.push(KEY_SEPARATOR);
+        }
+
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        !self.seen.insert(hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bson::doc;
+
+    use crate::config::DEFAULT_DEDUP_KEY;
+
+    #[test]
+    fn repeated_addresses_are_duplicates() {
+        let mut deduplicator: Deduplicator = Deduplicator::new(vec![DEFAULT_DEDUP_KEY.to_string()]);
+
+        assert!(!deduplicator.is_duplicate(&doc! { "icao24": "4CA1FA", "registration": "G-ABCD" }));
+        assert!(!deduplicator.is_duplicate(&doc! { "icao24": "4CA1FB", "registration": "G-ABCD" }));
+        assert!(deduplicator.is_duplicate(&doc! { "icao24": "4CA1FA", "registration": "D-EFGH" }));
+    }
+}
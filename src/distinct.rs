@@ -0,0 +1,127 @@
+use std::collections::BTreeSet;
+
+use bson::{Bson, Document};
+
+use serde::Serialize;
+
+use crate::sink::{RecordSink, SinkError};
+
+// The default cap on the number of distinct values kept by the distinct subcommand
+pub const DEFAULT_DISTINCT_LIMIT: usize = 10_000;
+
+// Collects the distinct values of one field instead of storing the records
+//
+// The values are kept sorted, and the run fails once there are more than the limit so a field
+// with a value per record, such as icao24, can't use unbounded memory
+pub struct DistinctValues {
+    field: String,
+    limit: usize,
+    values: BTreeSet<String>,
+}
+
+impl DistinctValues {
+    pub fn new(field: &str, limit: usize) -> Self {
+        DistinctValues {
+            field: field.to_string(),
+            limit,
+            values: BTreeSet::new(),
+        }
+    }
+
+    // The distinct values in sorted order
+    pub fn values(&self) -> &BTreeSet<String> {
+        &self.values
+    }
+
+    fn insert(&mut self, value: &Bson) -> Result<(), SinkError> {
+        let value: String = match value {
+            // Empty and missing values are not listed
+            Bson::String(value) if value.is_empty() => return Ok(()),
+            Bson::Null => return Ok(()),
+            Bson::String(value) => value.clone(),
+            // Each element of an --array-field is a value of its own
+            Bson::Array(values) => {
+                for value in values {
+                    self.insert(value)?;
+                }
+
+                return Ok(());
+            }
+            value => value.to_string(),
+        };
+
+        if !self.values.contains(&value) && self.values.len() >= self.limit {
+            return Err(SinkError::TooManyValues(self.limit));
+        }
+
+        self.values.insert(value);
+
+        Ok(())
+    }
+}
+
+impl<T> RecordSink<T> for DistinctValues
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    async fn add_record(&mut self, record: T) -> Result<(), SinkError> {
+        // Records that can't be converted have no value to add
+        let document: Document = match bson::to_document(&record) {
+            Ok(document) => document,
+            Err(_) => return Ok(()),
+        };
+
+        match document.get(&self.field) {
+            Some(value) => self.insert(value),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bson::doc;
+
+    async fn collect(distinct: &mut DistinctValues, documents: Vec<Document>) -> Result<(), SinkError> {
+        for document in documents {
+            distinct.add_record(document).await?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn values_are_distinct_and_sorted() {
+        let mut distinct: DistinctValues = DistinctValues::new("country", 10);
+
+        let documents: Vec<Document> = vec![
+            doc! { "country": "Ireland" },
+            doc! { "country": "France" },
+            doc! { "country": "" },
+            doc! { "country": "Ireland" },
+            doc! { "registration": "G-ABCD" },
+            doc! { "country": ["Spain", "France"] },
+        ];
+
+        assert!(collect(&mut distinct, documents).await.is_ok());
+        assert_eq!(distinct.values().iter().collect::<Vec<_>>(), ["France", "Ireland", "Spain"]);
+    }
+
+    #[tokio::test]
+    async fn more_values_than_the_limit_fail() {
+        let mut distinct: DistinctValues = DistinctValues::new("country", 2);
+
+        let documents: Vec<Document> = vec![
+            doc! { "country": "Ireland" },
+            doc! { "country": "France" },
+            doc! { "country": "Ireland" },
+        ];
+
+        assert!(collect(&mut distinct, documents).await.is_ok());
+
+        let result: Result<(), SinkError> = collect(&mut distinct, vec![doc! { "country": "Spain" }]).await;
+        assert!(matches!(result, Err(SinkError::TooManyValues(2))));
+    }
+}
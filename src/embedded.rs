@@ -0,0 +1,134 @@
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+// The embedded server only listens on the loopback interface
+pub const EMBEDDED_MONGO_HOST: &str = "127.0.0.1";
+
+// How long mongod has to start accepting connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Keep the WiredTiger cache small, the default is half the memory of the machine
+const CACHE_SIZE_GB: &str = "0.25";
+
+#[derive(Debug)]
+pub enum EmbeddedError {
+    IoError(std::io::Error),
+    Exited(ExitStatus),
+    Timeout(Duration),
+}
+
+impl From<std::io::Error> for EmbeddedError {
+    fn from(error: std::io::Error) -> Self {
+        EmbeddedError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for EmbeddedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EmbeddedError::IoError(error) => write!(f, "could not run mongod: {}", error),
+            EmbeddedError::Exited(status) => write!(f, "mongod exited during startup ({})", status),
+            EmbeddedError::Timeout(timeout) => write!(f, "mongod did not accept connections within {:.0?}", timeout),
+        }
+    }
+}
+
+// A throwaway mongod on localhost with its data in a temporary directory, both removed when it is stopped or dropped
+pub struct EmbeddedMongo {
+    child: Child,
+    port: u16,
+    data_dir: TempDir,
+}
+
+impl EmbeddedMongo {
+    // Start mongod from the given binary and wait until it accepts connections
+    pub async fn start(mongod: &Path) -> Result<Self, EmbeddedError> {
+        let data_dir: TempDir = tempfile::tempdir()?;
+        let port: u16 = free_port()?;
+
+        // Log to a file in the data directory so the output doesn't mix with the progress
+        let child: Child = Command::new(mongod)
+            .arg("--dbpath")
+            .arg(data_dir.path())
+            .arg("--logpath")
+            .arg(data_dir.path().join("mongod.log"))
+            .args(["--port", &port.to_string(), "--bind_ip", EMBEDDED_MONGO_HOST, "--wiredTigerCacheSizeGB", CACHE_SIZE_GB])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let mut embedded: EmbeddedMongo = EmbeddedMongo { child, port, data_dir };
+        embedded.wait_until_ready().await?;
+
+        Ok(embedded)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir.path()
+    }
+
+    // Stop mongod and wait for it to exit, the data directory is removed with self
+    pub async fn stop(mut self) -> Result<(), EmbeddedError> {
+        self.child.kill().await?;
+        Ok(())
+    }
+
+    async fn wait_until_ready(&mut self) -> Result<(), EmbeddedError> {
+        let start: Instant = Instant::now();
+
+        loop {
+            // A bad binary or option makes mongod exit straight away
+            if let Some(status) = self.child.try_wait()? {
+                return Err(EmbeddedError::Exited(status));
+            }
+
+            if TcpStream::connect((EMBEDDED_MONGO_HOST, self.port)).await.is_ok() {
+                return Ok(());
+            }
+
+            if start.elapsed() >= STARTUP_TIMEOUT {
+                return Err(EmbeddedError::Timeout(STARTUP_TIMEOUT));
+            }
+
+            tokio::time::sleep(STARTUP_POLL_INTERVAL).await;
+        }
+    }
+}
+
+// A port nothing is listening on, found by letting the OS pick one and releasing it for mongod
+fn free_port() -> std::io::Result<u16> {
+    let listener: TcpListener = TcpListener::bind((EMBEDDED_MONGO_HOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_missing_binary_is_reported() {
+        let result = EmbeddedMongo::start(Path::new("/nonexistent/mongod")).await;
+        assert!(matches!(result, Err(EmbeddedError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn a_binary_that_exits_is_reported() {
+        // false exits straight away, as mongod does with a bad option
+        let result = EmbeddedMongo::start(Path::new("false")).await;
+        assert!(matches!(result, Err(EmbeddedError::Exited(_))));
+    }
+}
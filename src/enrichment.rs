@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use csv_async::{AsyncDeserializer, AsyncReaderBuilder};
+
+use futures::stream::StreamExt;
+
+use serde::Deserialize;
+
+use crate::models::Aircraft;
+
+// Errors that can occur when loading the enrichment lookup file
+#[derive(Debug)]
+pub enum EnrichmentError {
+    IoError(std::io::Error),
+    CsvError(csv_async::Error),
+}
+
+impl From<std::io::Error> for EnrichmentError {
+    fn from(error: std::io::Error) -> Self {
+        EnrichmentError::IoError(error)
+    }
+}
+
+impl From<csv_async::Error> for EnrichmentError {
+    fn from(error: csv_async::Error) -> Self {
+        EnrichmentError::CsvError(error)
+    }
+}
+
+impl std::fmt::Display for EnrichmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EnrichmentError::IoError(error) => write!(f, "Enrichment file error: {}", error),
+            EnrichmentError::CsvError(error) => write!(f, "Enrichment file error: {}", error),
+        }
+    }
+}
+
+// A row of the lookup file, a standard comma separated CSV with a header row, for example
+//
+// manufacturerIcao,manufacturerName,categoryDescription
+// BOEING,Boeing,Large (75000 to 300000 lbs)
+//
+// Only manufacturerIcao is required, empty or missing columns leave the record unchanged
+#[derive(Deserialize)]
+struct LookupRow {
+    #[serde(rename = "manufacturerIcao")]
+    manufacturer_icao: String,
+    #[serde(rename = "manufacturerName", default)]
+    manufacturer_name: Option<String>,
+    #[serde(rename = "categoryDescription", default)]
+    category_description: Option<String>,
+}
+
+struct LookupEntry {
+    manufacturer_name: Option<String>,
+    category_description: Option<String>,
+}
+
+// Replacement manufacturer details, joined to the records on manufacturerIcao
+pub struct Enrichment {
+    entries: HashMap<String, LookupEntry>,
+}
+
+impl Enrichment {
+    pub async fn load(path: &Path) -> Result<Self, EnrichmentError> {
+        let file: tokio::fs::File = tokio::fs::File::open(path).await?;
+
+        let mut reader: AsyncDeserializer<tokio::fs::File> = AsyncReaderBuilder::new()
+            .trim(csv_async::Trim::All)
+            .create_deserializer(file);
+
+        let mut rows = reader.deserialize::<LookupRow>();
+        let mut entries: HashMap<String, LookupEntry> = HashMap::new();
+
+        // Later rows override earlier rows with the same key
+        while let Some(row) = rows.next().await {
+            let row: LookupRow = row?;
+
+            entries.insert(
+                join_key(&row.manufacturer_icao),
+                LookupEntry {
+                    manufacturer_name: row.manufacturer_name.filter(|value| !value.is_empty()),
+                    category_description: row.category_description.filter(|value| !value.is_empty()),
+                },
+            );
+        }
+
+        Ok(Enrichment { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Override the manufacturer fields of a record, returning whether a lookup entry matched
+    pub fn apply(&self, aircraft: &mut Aircraft) -> bool {
+        if aircraft.manufacturer_icao.is_empty() {
+            return false;
+        }
+
+        match self.entries.get(&join_key(&aircraft.manufacturer_icao)) {
+            Some(entry) => {
+                if let Some(manufacturer_name) = &entry.manufacturer_name {
+                    aircraft.manufacturer_name = manufacturer_name.clone();
+                }
+
+                if let Some(category_description) = &entry.category_description {
+                    aircraft.category_description = category_description.clone();
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// The OpenSky data mixes case and padding, so compare the keys trimmed and uppercased
+fn join_key(manufacturer_icao: &str) -> String {
+    manufacturer_icao.trim().to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use serde_json::{Map, Value};
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::models::field_names;
+
+    // An aircraft with every field empty apart from the manufacturer details
+    fn aircraft(manufacturer_icao: &str, manufacturer_name: &str) -> Aircraft {
+        let mut fields: Map<String, Value> = field_names::<Aircraft>()
+            .iter()
+            .map(|field| (field.to_string(), Value::from("")))
+            .collect();
+
+        fields.insert("manufacturerIcao".to_string(), Value::from(manufacturer_icao));
+        fields.insert("manufacturerName".to_string(), Value::from(manufacturer_name));
+
+        serde_json::from_value(Value::Object(fields)).unwrap()
+    }
+
+    async fn load(contents: &str) -> Enrichment {
+        let mut file: NamedTempFile = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+
+        Enrichment::load(file.path()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn overrides_matching_records() {
+        let enrichment: Enrichment = load(
+            "manufacturerIcao,manufacturerName,categoryDescription\n\
+             BOEING,Boeing,Large (75000 to 300000 lbs)\n\
+             AIRBUS,Airbus,\n",
+        )
+        .await;
+
+        assert_eq!(enrichment.len(), 2);
+
+        // The key is trimmed and compared case-insensitively
+        let mut boeing: Aircraft = aircraft(" boeing ", "BOEING COMPANY");
+        assert!(enrichment.apply(&mut boeing));
+        assert_eq!(boeing.manufacturer_name, "Boeing");
+        assert_eq!(boeing.category_description, "Large (75000 to 300000 lbs)");
+
+        // An empty column leaves the field unchanged
+        let mut airbus: Aircraft = aircraft("AIRBUS", "AIRBUS SAS");
+        airbus.category_description = "Unknown".to_string();
+        assert!(enrichment.apply(&mut airbus));
+        assert_eq!(airbus.manufacturer_name, "Airbus");
+        assert_eq!(airbus.category_description, "Unknown");
+    }
+
+    #[tokio::test]
+    async fn leaves_other_records_unchanged() {
+        let enrichment: Enrichment = load("manufacturerIcao,manufacturerName\nBOEING,Boeing\n").await;
+
+        let mut cessna: Aircraft = aircraft("CESSNA", "Cessna");
+        assert!(!enrichment.apply(&mut cessna));
+        assert_eq!(cessna.manufacturer_name, "Cessna");
+
+        let mut blank: Aircraft = aircraft("", "");
+        assert!(!enrichment.apply(&mut blank));
+    }
+
+    #[tokio::test]
+    async fn later_rows_override_earlier_rows() {
+        let enrichment: Enrichment = load("manufacturerIcao,manufacturerName\nBOEING,Boeing\nboeing,The Boeing Company\n").await;
+
+        let mut boeing: Aircraft = aircraft("BOEING", "");
+        enrichment.apply(&mut boeing);
+
+        assert_eq!(enrichment.len(), 1);
+        assert_eq!(boeing.manufacturer_name, "The Boeing Company");
+    }
+}
@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+// Appended to the name of each file kept by --keep-partial-output after a failed run
+pub const PARTIAL_SUFFIX: &str = "partial";
+
+// Errors that can occur when writing output files
+#[derive(Debug)]
+pub enum OutputError {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+}
+
+impl From<std::io::Error> for OutputError {
+    fn from(error: std::io::Error) -> Self {
+        OutputError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for OutputError {
+    fn from(error: serde_json::Error) -> Self {
+        OutputError::JsonError(error)
+    }
+}
+
+impl std::fmt::Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputError::IoError(error) => write!(f, "Output IO error: {}", error),
+            OutputError::JsonError(error) => write!(f, "Output JSON error: {}", error),
+        }
+    }
+}
+
+// Writes records as newline delimited JSON
+//
+// With a record limit the output rolls over to numbered files once each one is full,
+// e.g. aircraft.jsonl is written as aircraft.0001.jsonl, aircraft.0002.jsonl, ...
+pub struct JsonLinesWriter {
+    path: PathBuf,
+    max_records: Option<u64>,
+    files: Vec<PathBuf>,
+    records: u64,
+    records_in_file: u64,
+    writer: Option<BufWriter<File>>,
+    keep_partial: bool,
+}
+
+impl JsonLinesWriter {
+    pub fn new(path: &Path, max_records: Option<u64>) -> Self {
+        JsonLinesWriter {
+            path: path.to_path_buf(),
+            max_records,
+            files: Vec::new(),
+            records: 0,
+            records_in_file: 0,
+            writer: None,
+            keep_partial: false,
+        }
+    }
+
+    pub fn set_keep_partial(&mut self, keep_partial: bool) {
+        // After a failure, rename the files written so far rather than removing them
+        self.keep_partial = keep_partial;
+    }
+
+    pub async fn write_record<T>(&mut self, record: &T) -> Result<(), OutputError>
+    where
+        T: Serialize,
+    {
+        // Open the first file, or roll over to the next one when the current file is full
+        let full: bool = self.max_records.is_some_and(|max| self.records_in_file >= max);
+
+        if self.writer.is_none() || full {
+            self.open_next_file().await?;
+        }
+
+        // Serialize the record onto a single line
+        let mut line: Vec<u8> = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        if let Some(writer) = &mut self.writer {
+            writer.write_all(&line).await?;
+        }
+
+        self.records += 1;
+        self.records_in_file += 1;
+
+        Ok(())
+    }
+
+    pub async fn finish(&mut self) -> Result<(), OutputError> {
+        // Always create an output file, even if there were no records
+        if self.files.is_empty() {
+            self.open_next_file().await?;
+        }
+
+        if let Some(writer) = &mut self.writer {
+            writer.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    // Remove the files written so far after a failure, or rename them with a .partial suffix if
+    // they are to be kept, so an incomplete export is never taken for a complete one
+    pub async fn abort(&mut self) -> Result<(), OutputError> {
+        // Write out what is buffered if it can be, a full disk may be why the run failed
+        if let Some(mut writer) = self.writer.take() {
+            let _ = writer.flush().await;
+        }
+
+        for file in &self.files {
+            match self.keep_partial {
+                true => tokio::fs::rename(file, partial_path(file)).await?,
+                false => tokio::fs::remove_file(file).await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn keep_partial(&self) -> bool {
+        self.keep_partial
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    async fn open_next_file(&mut self) -> Result<(), OutputError> {
+        // Flush the file being closed
+        if let Some(writer) = &mut self.writer {
+            writer.flush().await?;
+        }
+
+        // Only number the files if the output can roll over
+        let path: PathBuf = match self.max_records {
+            Some(_) => numbered_path(&self.path, self.files.len() + 1),
+            None => self.path.clone(),
+        };
+
+        self.writer = Some(BufWriter::new(File::create(&path).await?));
+        self.files.push(path);
+        self.records_in_file = 0;
+
+        Ok(())
+    }
+}
+
+// Insert the file number before the extension
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem: String = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let file_name: String = match path.extension() {
+        Some(extension) => format!("{}.{:04}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.{:04}", stem, number),
+    };
+
+    path.with_file_name(file_name)
+}
+
+// The name a partial file is kept under, with the suffix after the whole file name
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(PARTIAL_SUFFIX);
+
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn aborted_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer: JsonLinesWriter = JsonLinesWriter::new(&dir.path().join("aircraft.jsonl"), Some(2));
+
+        for icao24 in ["A1", "B2", "C3"] {
+            writer.write_record(&doc! { "icao24": icao24 }).await.unwrap();
+        }
+
+        assert_eq!(writer.files().len(), 2);
+        writer.abort().await.unwrap();
+
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn aborted_files_can_be_kept_as_partial() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer: JsonLinesWriter = JsonLinesWriter::new(&dir.path().join("aircraft.jsonl"), None);
+        writer.set_keep_partial(true);
+
+        writer.write_record(&doc! { "icao24": "A1" }).await.unwrap();
+        writer.abort().await.unwrap();
+
+        assert!(!dir.path().join("aircraft.jsonl").exists());
+        let kept: String = std::fs::read_to_string(dir.path().join("aircraft.jsonl.partial")).unwrap();
+        assert_eq!(kept, "{\"icao24\":\"A1\"}\n");
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_leaves_nothing_to_clean_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer: JsonLinesWriter = JsonLinesWriter::new(&dir.path().join("missing").join("aircraft.jsonl"), None);
+
+        assert!(writer.write_record(&doc! { "icao24": "A1" }).await.is_err());
+        assert!(writer.abort().await.is_ok());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,43 @@
+use reqwest::header::{HeaderName, HeaderValue};
+
+// Sent with every download request unless a User-Agent is given with --header, so the server can
+// tell who is downloading
+pub const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// Parse a --header of the form "Name: Value"
+//
+// The value is marked as sensitive, as it is often a token, so it isn't printed with the settings
+pub fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, header_value) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected a header of the form \"Name: Value\", got {:?}", value))?;
+
+    let name: HeaderName = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| format!("{:?} is not a valid header name", name.trim()))?;
+
+    let mut header_value: HeaderValue = HeaderValue::from_str(header_value.trim()).map_err(|_| format!("the value of the {} header can't be sent in a request", name))?;
+    header_value.set_sensitive(true);
+
+    Ok((name, header_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_read_as_name_and_value() {
+        let (name, value) = parse_header("X-Api-Token:  abc123 ").unwrap();
+
+        assert_eq!(name, "x-api-token");
+        assert_eq!(value, "abc123");
+        assert!(value.is_sensitive());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        assert!(parse_header("X-Api-Token abc123").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+        assert!(parse_header(": value").is_err());
+        assert!(parse_header("From: a\nb").is_err());
+    }
+}
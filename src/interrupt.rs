@@ -0,0 +1,110 @@
+use std::pin::pin;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::sync::Notify;
+
+use crate::report;
+use crate::ExitCodes;
+
+// Signals are per process, so the first Ctrl-C sets this one flag for the whole run
+static CTRL_C: Interrupt = Interrupt::new();
+
+// A flag that can be waited on, set once to stop reading new records
+pub struct Interrupt {
+    interrupted: AtomicBool,
+    notify: Notify,
+}
+
+impl Interrupt {
+    pub const fn new() -> Self {
+        Interrupt {
+            interrupted: AtomicBool::new(false),
+            notify: Notify::const_new(),
+        }
+    }
+
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    // Return once the flag has been set
+    pub async fn interrupted(&self) {
+        let mut notified = pin!(self.notify.notified());
+
+        // Register for the notification before checking, so an interrupt in between isn't missed
+        notified.as_mut().enable();
+
+        if !self.is_interrupted() {
+            notified.await;
+        }
+    }
+}
+
+impl Default for Interrupt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Whether Ctrl-C has been pressed during the run
+pub fn is_interrupted() -> bool {
+    CTRL_C.is_interrupted()
+}
+
+// Return once Ctrl-C has been pressed
+pub async fn interrupted() {
+    CTRL_C.interrupted().await
+}
+
+// Finish the run cleanly on the first Ctrl-C, storing the records already read, and abort on the second
+pub fn handle_ctrl_c() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+
+        CTRL_C.interrupt();
+
+        let text: String = "Warning: interrupted, storing the records already read, press Ctrl-C again to abort".to_string();
+        report::warning(&text);
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let text: String = "Error: aborted, the records not yet inserted have been lost".to_string();
+            report::error(&text);
+            exit(ExitCodes::Aborted as i32);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn waiters_are_woken_by_an_interrupt() {
+        let interrupt: Arc<Interrupt> = Arc::new(Interrupt::new());
+
+        let waiting: Arc<Interrupt> = interrupt.clone();
+        let waiter = tokio::spawn(async move { waiting.interrupted().await });
+
+        // Let the waiter register before interrupting
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!interrupt.is_interrupted());
+        interrupt.interrupt();
+
+        assert!(tokio::time::timeout(Duration::from_secs(1), waiter).await.is_ok());
+        assert!(interrupt.is_interrupted());
+
+        // Once interrupted, waiting returns straight away
+        assert!(tokio::time::timeout(Duration::from_secs(1), interrupt.interrupted()).await.is_ok());
+    }
+}
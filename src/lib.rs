@@ -0,0 +1,2349 @@
+pub mod checkpoint;
+pub mod checksum;
+pub mod compare;
+pub mod config;
+pub mod config_file;
+pub mod csv_dialect;
+pub mod db_writer;
+pub mod dedup;
+pub mod distinct;
+#[cfg(feature = "embedded-db")]
+pub mod embedded;
+pub mod enrichment;
+pub mod file_writer;
+pub mod headers;
+pub mod interrupt;
+pub mod logging;
+pub mod memory;
+pub mod metrics;
+pub mod models;
+pub mod pause;
+pub mod progress;
+pub mod proxy;
+pub mod raw_lines;
+pub mod record_downloader;
+pub mod report;
+pub mod sample;
+pub mod serialization;
+pub mod signing;
+pub mod sink;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+pub mod summary;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use bson::{doc, Bson, Document};
+
+use chrono::Datelike;
+
+use colored::Colorize;
+
+use indicatif::HumanBytes;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+
+use tracing::info;
+
+use checkpoint::{Checkpoint, SavedCheckpoint};
+use checksum::{parse_sidecar, ChecksumAlgorithm};
+use compare::{compare_sorted, CollectionDiff, CollectionTarget};
+use config::{Config, Mode, Source};
+use csv_dialect::DatasetProfile;
+use db_writer::{DatabaseError, DatabaseWriter, IndexSpec, MongoClient, ShardKey, Topology};
+use dedup::Deduplicator;
+#[cfg(feature = "embedded-db")]
+use embedded::{EmbeddedMongo, EMBEDDED_MONGO_HOST};
+use distinct::DistinctValues;
+use enrichment::Enrichment;
+use file_writer::{JsonLinesWriter, PARTIAL_SUFFIX};
+use memory::MemoryBudget;
+use metrics::{metrics, MetricsSnapshot};
+use models::{normalise_country, Aircraft, Airport, Icao24Padding, Model, SchemaDrift, AGE_YEARS_FIELD};
+use progress::RunProgress;
+use record_downloader::{DownloadError, DownloadInfo, RecordInfo};
+use sample::{check_sample, RecordSample, SampleCheck};
+use serialization::{OutputOptions, OutputRecord};
+use sink::{DryRunSink, FilteredOutSink, RecordSink, SinkError};
+#[cfg(feature = "statsd")]
+use statsd::StatsdReporter;
+use summary::{DatasetSummary, DatasetsSummary, RunSummary, Summary};
+
+// The field --upsert replaces the documents by, which is also indexed so each replace is a lookup
+const UPSERT_KEY: &str = "icao24";
+
+// A --filtered-out target with this prefix names a collection in the same database rather than a file
+const FILTERED_OUT_COLLECTION_PREFIX: &str = "collection:";
+
+// Appended to the collection name for the staging collection used with --staging or --max-errors
+const STAGING_COLLECTION_SUFFIX: &str = "_loading";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCodes {
+    Success = 0,
+    DownloadError = 1,
+    DatabaseError = 2,
+    JoinError = 3,
+    ConfigError = 4,
+    ValidationError = 5,
+    CheckpointError = 6,
+    OutputError = 7,
+    SchemaDrift = 8,
+    CollectionsDiffer = 9,
+    Interrupted = 10,
+    Aborted = 11,
+}
+
+// Run the download with the resolved settings, returning the process exit code
+pub async fn run(config: Config) -> ExitCodes {
+    run_with_summary(config).await.exit_code
+}
+
+// Run the download with the resolved settings, returning the exit code with the counts and time for the run
+//
+// The counts come from the run metrics, which are shared by the process, so runs shouldn't overlap
+pub async fn run_with_summary(config: Config) -> RunSummary {
+    let start: Instant = Instant::now();
+    let before: MetricsSnapshot = metrics().snapshot();
+
+    let exit_code: ExitCodes = match config.statsd_addr.clone() {
+        Some(statsd_addr) => run_with_statsd(&statsd_addr, config).await,
+        None => run_mode(config).await,
+    };
+
+    RunSummary::new(exit_code, metrics().snapshot().since(&before), start.elapsed())
+}
+
+// Send the counters to StatsD as the run goes, with the run time and exit code at the end
+#[cfg(feature = "statsd")]
+async fn run_with_statsd(statsd_addr: &str, config: Config) -> ExitCodes {
+    let reporter: StatsdReporter = match StatsdReporter::start(statsd_addr).await {
+        Ok(reporter) => reporter,
+        Err(error) => {
+            let text = format!("Error: could not set up StatsD metrics for {}: {}", statsd_addr, report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::ConfigError;
+        }
+    };
+
+    let start: Instant = Instant::now();
+    let exit_code: ExitCodes = run_mode(config).await;
+    reporter.finish(start.elapsed(), exit_code as i32).await;
+
+    exit_code
+}
+
+#[cfg(not(feature = "statsd"))]
+async fn run_with_statsd(_statsd_addr: &str, _config: Config) -> ExitCodes {
+    let text: String = "Error: --statsd-addr is not available, rebuild with --features statsd to enable it".to_string();
+    report::error(&text);
+    ExitCodes::ConfigError
+}
+
+async fn run_mode(config: Config) -> ExitCodes {
+    // Connected when the first collection is opened
+    let client: RunClient = RunClient::default();
+
+    // Modes that don't load anything
+    match config.mode {
+        Mode::Unlock => return unlock_collection(&config, &client).await,
+        Mode::Estimate => return estimate(&config).await,
+        Mode::CheckSchema => return check_schema(&config).await,
+        Mode::Load | Mode::Distinct | Mode::GroupCount | Mode::CompareCollections | Mode::Index => {}
+    }
+
+    // Check the settings before any work is done
+    if let Err(error) = config.validate() {
+        let text = format!("Error: {}", error);
+        report::error(&text);
+        return ExitCodes::ConfigError;
+    }
+
+    // Count the stored documents by a field without downloading anything
+    if config.mode == Mode::GroupCount {
+        return group_count(&config, &client).await;
+    }
+
+    // Create the indexes on the stored documents without downloading anything
+    if config.mode == Mode::Index {
+        return update_indexes(&config, &client).await;
+    }
+
+    // Compare two collections without downloading anything
+    if let (Mode::CompareCollections, Some((left, right))) = (config.mode, &config.compare_targets) {
+        return compare_collections(left, right, &config).await;
+    }
+
+    // Load each dataset from the config file in turn
+    if config.mode == Mode::Load && !config.datasets.is_empty() {
+        return load_datasets(config, &client).await;
+    }
+
+    load_source(config, &client).await
+}
+
+// The MongoDB client of a run, created along with the first writer so a run that stores nothing doesn't
+// start one, and shared by the writers of each dataset and the --filtered-out collection
+#[derive(Default)]
+struct RunClient(OnceCell<MongoClient>);
+
+impl RunClient {
+    // A writer for a collection on the server of the run
+    async fn writer<T>(&self, config: &Config, database_name: &str, collection_name: &str) -> Result<DatabaseWriter<T>, DatabaseError>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let client: &MongoClient = self
+            .0
+            .get_or_try_init(|| MongoClient::new(&config.mongo_host, config.mongo_port, config.mongo_credentials.as_ref(), config.mongo_tls.as_ref(), config.connect_timeout))
+            .await?;
+
+        DatabaseWriter::new(client, database_name, collection_name).await
+    }
+}
+
+// Read the source, storing or exporting the records, or listing their distinct values
+async fn load_source(mut config: Config, client: &RunClient) -> ExitCodes {
+    // Read the expected checksum from the sidecar before the download starts
+    if let Some(checksum_url) = &config.checksum_url {
+        match fetch_expected_checksum(checksum_url, &config).await {
+            Ok(expected_checksum) => config.expected_checksum = Some(expected_checksum),
+            Err(error_code) => return error_code,
+        }
+    }
+
+    // Load the enrichment lookup file if one was given
+    let enrichment: Option<Enrichment> = match &config.enrich_file {
+        Some(path) => match Enrichment::load(path).await {
+            Ok(enrichment) => {
+                let text: String = format!("Loaded {} manufacturers from {}", enrichment.len(), path.display());
+                info!("{}", text);
+                Some(enrichment)
+            }
+            Err(error) => {
+                let text = format!("Error: {}", error);
+                report::error(&text);
+                return ExitCodes::ConfigError;
+            }
+        },
+        None => None,
+    };
+
+    // List the distinct values of a field without storing anything
+    if config.mode == Mode::Distinct {
+        return download_distinct(&config, enrichment.as_ref()).await;
+    }
+
+    // Parse and count the records without writing them anywhere
+    if config.dry_run {
+        return dry_run(&config, enrichment.as_ref()).await;
+    }
+
+    // Load into a throwaway MongoDB if requested
+    if let Some(mongod) = &config.embedded_mongod {
+        return load_embedded(mongod, &config, enrichment.as_ref()).await;
+    }
+
+    // Airports are stored through the same pipeline, Config::validate has rejected the options that only apply to aircraft
+    if config.dataset == DatasetProfile::Airports {
+        return connect_and_store::<Airport>(&config, None, client).await;
+    }
+
+    // Write to files if requested, otherwise store the records in MongoDB
+    match &config.output {
+        Some(path) => download_and_export(path, &config, enrichment.as_ref(), client).await,
+        None => connect_and_store::<Aircraft>(&config, enrichment.as_ref(), client).await,
+    }
+}
+
+// Load the datasets one after another with the same settings, each into its own collection, then report them together
+//
+// A failed dataset doesn't stop the others, the exit code is that of the first to fail
+async fn load_datasets(config: Config, client: &RunClient) -> ExitCodes {
+    let start: Instant = Instant::now();
+    let mut datasets: Vec<DatasetSummary> = Vec::with_capacity(config.datasets.len());
+    let mut exit_code: ExitCodes = ExitCodes::Success;
+
+    for (index, dataset) in config.datasets.iter().enumerate() {
+        let text: String = format!("Loading dataset {} of {}, {} into {}", index + 1, config.datasets.len(), dataset.name(), dataset.collection);
+        info!("{}", text);
+
+        // The dataset's column renames go on top of the shared ones
+        let mut column_renames: HashMap<String, String> = config.column_renames.clone();
+        column_renames.extend(dataset.columns.clone());
+
+        let dataset_config: Config = Config {
+            source: Source::from_location(dataset.url.clone()),
+            datasets: Vec::new(),
+            collection_name: dataset.collection.clone(),
+            column_renames,
+            ..config.clone()
+        };
+
+        // Count this dataset's records by the growth of the run metrics
+        let dataset_start: Instant = Instant::now();
+        let before: MetricsSnapshot = metrics().snapshot();
+        let dataset_exit_code: ExitCodes = load_source(dataset_config, client).await;
+        let counted: MetricsSnapshot = metrics().snapshot().since(&before);
+
+        datasets.push(DatasetSummary {
+            name: dataset.name().to_string(),
+            collection: dataset.collection.clone(),
+            exit_code: dataset_exit_code as i32,
+            records_read: counted.records_read,
+            records_stored: counted.records_stored,
+            errors: counted.errors,
+            duration_seconds: dataset_start.elapsed().as_secs_f64(),
+        });
+
+        if exit_code == ExitCodes::Success {
+            exit_code = dataset_exit_code;
+        }
+
+        // Ctrl-C stops the whole run, not just the dataset being loaded
+        if interrupt::is_interrupted() {
+            break;
+        }
+    }
+
+    DatasetsSummary::new(datasets, start.elapsed()).print(config.summary_format);
+
+    exit_code
+}
+
+async fn estimate(config: &Config) -> ExitCodes {
+    let url: &str = match &config.source {
+        Source::Url(url) => url,
+        _ => {
+            let text: String = "Error: --estimate-only needs an http(s) URL".to_string();
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    // Print that we are checking the file
+    let text: String = format!("Checking {}", url);
+    info!("{}", text);
+
+    // Sign the requests if the mirror needs it, sending them through any proxy
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+    download_info.set_request_signer(config.request_signer.clone());
+    download_info.set_proxy(config.proxy.clone());
+    download_info.set_request_headers(config.request_headers.clone());
+    download_info.set_http_timeout(config.http_timeout);
+
+    match download_info.estimate(url).await {
+        Ok(remote_file_info) => {
+            let content_length: String = match remote_file_info.content_length {
+                Some(length) => format!("{} ({} bytes)", HumanBytes(length), length),
+                None => "unknown".to_string(),
+            };
+
+            let text: String = format!("Content length: {}", content_length);
+            println!("{}", text.green().bold());
+
+            let text: String = format!("Last modified: {}", remote_file_info.last_modified.as_deref().unwrap_or("unknown"));
+            println!("{}", text.green().bold());
+
+            let text: String = format!("Content type: {}", remote_file_info.content_type.as_deref().unwrap_or("unknown"));
+            println!("{}", text.green().bold());
+
+            ExitCodes::Success
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DownloadError
+        }
+    }
+}
+
+async fn check_schema(config: &Config) -> ExitCodes {
+    // Read the header with the same dialect and column renames as a load
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+    configure_download(&mut download_info, config);
+
+    let result: Result<Vec<String>, DownloadError<Aircraft>> = match &config.source {
+        Source::Url(url) => {
+            let text: String = format!("Reading the header of {}", url);
+            info!("{}", text);
+
+            download_info.download_columns(url).await
+        }
+        Source::File(path) => {
+            let text: String = format!("Reading the header of {}", path.display());
+            info!("{}", text);
+
+            match tokio::fs::File::open(path).await {
+                Ok(file) => download_info.read_columns(file).await,
+                Err(error) => Err(error.into()),
+            }
+        }
+        Source::Stdin => download_info.read_columns(tokio::io::stdin()).await,
+        Source::S3(_) => {
+            let text: String = "Error: check-schema needs an http(s) URL or a file".to_string();
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    let columns: Vec<String> = match result {
+        Ok(columns) => columns,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    let drift: SchemaDrift = match config.dataset {
+        DatasetProfile::Airports => SchemaDrift::new::<Airport>(columns.iter().map(String::as_str)),
+        DatasetProfile::Aircraft | DatasetProfile::Rfc4180 => SchemaDrift::new::<Aircraft>(columns.iter().map(String::as_str)),
+    };
+
+    if drift.is_empty() {
+        let text: String = format!("The {} columns match the model", columns.len());
+        info!(success = true, "{}", text);
+        return ExitCodes::Success;
+    }
+
+    // List the columns the model would need to add or remove to match the file
+    if !drift.extra.is_empty() {
+        println!("{}", "Added columns, in the file but not the model:".yellow().bold());
+
+        for column in &drift.extra {
+            println!("  + {}", column);
+        }
+    }
+
+    if !drift.missing.is_empty() {
+        println!("{}", "Removed columns, in the model but not the file:".yellow().bold());
+
+        for field in &drift.missing {
+            println!("  - {}", field);
+        }
+    }
+
+    let text: String = format!(
+        "Error: the model has drifted from the file, {} added and {} removed columns",
+        drift.extra.len(),
+        drift.missing.len()
+    );
+    report::error(&text);
+
+    ExitCodes::SchemaDrift
+}
+
+async fn unlock_collection(config: &Config, client: &RunClient) -> ExitCodes {
+    let mongo_host: &str = &config.mongo_host;
+    let database_name: &str = &config.database_name;
+    let collection_name: &str = &config.collection_name;
+
+    // Print that we are connecting to the database
+    let text: String = format!("Connecting to MongoDB on {}:{}", mongo_host, config.mongo_port);
+    info!("{}", text);
+
+    let result = match client.writer::<OutputRecord<Aircraft>>(config, database_name, collection_name).await {
+        Ok(db_writer) => db_writer.unlock_collection().await,
+        Err(error) => Err(error),
+    };
+
+    match result {
+        Ok(_) => {
+            let text: String = format!("Unlocked {}.{}", database_name, collection_name);
+            info!(success = true, "{}", text);
+            ExitCodes::Success
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DatabaseError
+        }
+    }
+}
+
+async fn group_count(config: &Config, client: &RunClient) -> ExitCodes {
+    let mongo_host: &str = &config.mongo_host;
+    let database_name: &str = &config.database_name;
+    let collection_name: &str = &config.collection_name;
+
+    // Print that we are connecting to the database
+    let text: String = format!("Connecting to MongoDB on {}:{}", mongo_host, config.mongo_port);
+    info!("{}", text);
+
+    let result = match client.writer::<OutputRecord<Aircraft>>(config, database_name, collection_name).await {
+        Ok(db_writer) => db_writer.group_count(&config.output_options.stored_name(&config.group_field), config.group_top).await,
+        Err(error) => Err(error),
+    };
+
+    let groups: Vec<(String, u64)> = match result {
+        Ok(groups) => groups,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    };
+
+    // Print the counts right-aligned, one group per line
+    let text: String = format!("Top {} values of {} in {}.{}:", groups.len(), config.group_field, database_name, collection_name);
+    println!("{}", text.yellow().bold());
+
+    let width: usize = groups.iter().map(|(_, count)| count.to_string().len()).max().unwrap_or(0);
+
+    for (value, count) in &groups {
+        println!("{:>width$}  {}", count, value, width = width);
+    }
+
+    ExitCodes::Success
+}
+
+async fn update_indexes(config: &Config, client: &RunClient) -> ExitCodes {
+    let mongo_host: &str = &config.mongo_host;
+    let database_name: &str = &config.database_name;
+    let collection_name: &str = &config.collection_name;
+
+    // The indexes given on the command line, or those a load creates, on the stored field names
+    let indexes: Vec<IndexSpec> = match config.index_specs.is_empty() {
+        true => index_fields(config),
+        false => config
+            .index_specs
+            .iter()
+            .map(|index| IndexSpec {
+                keys: index.keys.iter().map(|(field, order)| (config.output_options.stored_name(field), *order)).collect(),
+                unique: index.unique,
+            })
+            .collect(),
+    };
+
+    // Print that we are connecting to the database
+    let text: String = format!("Connecting to MongoDB on {}:{}", mongo_host, config.mongo_port);
+    info!("{}", text);
+
+    let db_writer = match client.writer::<OutputRecord<Aircraft>>(config, database_name, collection_name).await {
+        Ok(db_writer) => db_writer,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    };
+
+    // Drop any index with the same keys, so it is rebuilt from scratch
+    if config.recreate_indexes {
+        for index in &indexes {
+            match db_writer.drop_index(index).await {
+                Ok(0) => {}
+                Ok(_) => {
+                    let text: String = format!("Dropped the index on {}", index);
+                    info!("{}", text);
+                }
+                Err(error) => {
+                    let text = format!("Error dropping the index on {}: {}", index, error);
+                    report::error(&text);
+                    return ExitCodes::DatabaseError;
+                }
+            }
+        }
+    }
+
+    // Print that we are creating the indexes
+    let text: String = format!("Creating indexes on {}.{}", database_name, collection_name);
+    info!("{}", text);
+
+    let mut index_failed: bool = false;
+
+    for (index, result) in db_writer.create_indexes(&indexes).await {
+        match result {
+            Ok(duration) => {
+                let text: String = format!("Index on {} created in {:.2?}", index, duration);
+                info!(success = true, "{}", text);
+            }
+            Err(error) => {
+                let text = format!("Error creating index on {}: {}", index, error);
+                report::error(&text);
+                index_failed = true;
+            }
+        }
+    }
+
+    // List every index on the collection, including those this run didn't create
+    match db_writer.list_indexes().await {
+        Ok(existing) => {
+            let text: String = format!("Indexes on {}.{}:", database_name, collection_name);
+            println!("{}", text.yellow().bold());
+
+            for (name, keys) in existing {
+                println!("  {}: {}", name, keys);
+            }
+        }
+        Err(error) => {
+            let text = format!("Error listing the indexes: {}", error);
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    }
+
+    match index_failed {
+        true => ExitCodes::DatabaseError,
+        false => ExitCodes::Success,
+    }
+}
+
+async fn compare_collections(left: &CollectionTarget, right: &CollectionTarget, config: &Config) -> ExitCodes {
+    let mut cursors: Vec<mongodb::Cursor<Document>> = Vec::with_capacity(2);
+
+    // The key as it is stored, if the fields were renamed
+    let compare_key: String = config.output_options.stored_name(&config.compare_key);
+
+    for target in [left, right] {
+        // Print that we are connecting to the database
+        let text: String = format!("Reading {} sorted on {}", target, compare_key);
+        info!("{}", text);
+
+        // The targets may be on different servers, so each has its own client
+        let result = match MongoClient::new(&target.host, target.port, config.mongo_credentials.as_ref(), config.mongo_tls.as_ref(), config.connect_timeout).await {
+            Ok(client) => match DatabaseWriter::<Document>::new(&client, &target.database_name, &target.collection_name).await {
+                Ok(db_writer) => db_writer.sorted_documents(&compare_key).await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(cursor) => cursors.push(cursor),
+            Err(error) => {
+                let text = format!("Error: {}", report::with_causes(&error));
+                report::error(&text);
+                return ExitCodes::DatabaseError;
+            }
+        }
+    }
+
+    let (Some(right_cursor), Some(left_cursor)) = (cursors.pop(), cursors.pop()) else {
+        return ExitCodes::DatabaseError;
+    };
+
+    // Both are read in key order, so only one document from each is held at a time
+    let diff: CollectionDiff = match compare_sorted(&compare_key, left_cursor, right_cursor).await {
+        Ok(diff) => diff,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    };
+
+    diff.print(left, right, config.summary_format);
+
+    match diff.is_empty() {
+        true => {
+            let text: String = "The collections match".to_string();
+            info!(success = true, "{}", text);
+            ExitCodes::Success
+        }
+        false => ExitCodes::CollectionsDiffer,
+    }
+}
+
+// Start mongod, load the records into it and keep it running to be queried until Ctrl-C is pressed
+#[cfg(feature = "embedded-db")]
+async fn load_embedded(mongod: &Path, config: &Config, enrichment: Option<&Enrichment>) -> ExitCodes {
+    let text: String = format!("Starting an embedded MongoDB with {}", mongod.display());
+    info!("{}", text);
+
+    let embedded: EmbeddedMongo = match EmbeddedMongo::start(mongod).await {
+        Ok(embedded) => embedded,
+        Err(error) => {
+            let text = format!("Error: {}", error);
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    };
+
+    let text: String = format!("Embedded MongoDB started on port {}, data in {}", embedded.port(), embedded.data_dir().display());
+    info!(success = true, "{}", text);
+
+    // Point the load at the embedded server
+    let config: Config = Config {
+        mongo_host: EMBEDDED_MONGO_HOST.to_string(),
+        mongo_port: embedded.port(),
+        mongo_credentials: None,
+        mongo_tls: None,
+        ..config.clone()
+    };
+
+    // A client for the embedded server, rather than the one the run would have used
+    let exit_code: ExitCodes = connect_and_store::<Aircraft>(&config, enrichment, &RunClient::default()).await;
+
+    // Leave the server up to be queried, unless the load was interrupted
+    if !interrupt::is_interrupted() {
+        let text: String = format!(
+            "Query the records with mongosh mongodb://{}:{}/{}, press Ctrl-C to stop the embedded MongoDB",
+            EMBEDDED_MONGO_HOST,
+            embedded.port(),
+            config.database_name
+        );
+        info!(notice = true, "{}", text);
+
+        interrupt::interrupted().await;
+    }
+
+    match embedded.stop().await {
+        Ok(_) => {
+            let text: String = "Embedded MongoDB stopped and its data removed".to_string();
+            info!(success = true, "{}", text);
+        }
+        Err(error) => {
+            let text = format!("Warning: could not stop the embedded MongoDB: {}", error);
+            report::warning(&text);
+        }
+    }
+
+    exit_code
+}
+
+#[cfg(not(feature = "embedded-db"))]
+async fn load_embedded(_mongod: &Path, _config: &Config, _enrichment: Option<&Enrichment>) -> ExitCodes {
+    let text: String = "Error: --embedded-db is not available, rebuild with --features embedded-db to enable it".to_string();
+    report::error(&text);
+    ExitCodes::ConfigError
+}
+
+async fn connect_and_store<D>(config: &Config, enrichment: Option<&Enrichment>, client: &RunClient) -> ExitCodes
+where
+    D: Model,
+{
+    let mongo_host: &str = &config.mongo_host;
+    let database_name: &str = &config.database_name;
+    let collection_name: &str = &config.collection_name;
+
+    // Load into a staging collection if requested, so a failed load leaves the collection untouched
+    let staging_name: Option<String> = config
+        .staging
+        .then(|| format!("{}{}", collection_name, STAGING_COLLECTION_SUFFIX));
+
+    // Print that we are connecting to the database
+    let text: String = format!("Connecting to MongoDB on {}:{}", mongo_host, config.mongo_port);
+    info!("{}", text);
+
+    // Create a new database writer, setting the exit code
+    let load_name: &str = staging_name.as_deref().unwrap_or(collection_name);
+
+    match client.writer::<OutputRecord<D>>(config, database_name, load_name).await {
+        Ok(mut db_writer) => {
+            // Set how many records are sent in each insert
+            db_writer.set_chunk_size(config.chunk_size);
+
+            // Serialise the inserts if the order must be preserved
+            db_writer.set_preserve_order(config.preserve_order);
+
+            // Bound the inserts running at once, so the server's connection pool isn't swamped
+            db_writer.set_max_concurrent_inserts(config.max_concurrent_inserts);
+
+            // Wait for the confirmation asked for before counting a chunk as stored
+            db_writer.set_write_concern(config.write_concern);
+
+            // Update the collection in place if requested
+            if config.upsert {
+                db_writer.set_upsert_key(&config.output_options.stored_name(UPSERT_KEY));
+            }
+
+            // Print that we are connected to the database, showing the database and collection names
+            let text: String = format!(
+                "Connected to MongoDB on {}:{} - Database: {} - Collection: {}",
+                mongo_host, config.mongo_port, database_name, collection_name
+            );
+            info!(success = true, "{}", text);
+
+            // Download and store the records, counting those stored by the growth of the run metrics
+            let before: MetricsSnapshot = metrics().snapshot();
+            let exit_code: ExitCodes = download_and_store(&mut db_writer, config, enrichment, client).await;
+            let records_stored: u64 = metrics().snapshot().since(&before).records_stored;
+
+            let exit_code: ExitCodes = match staging_name {
+                Some(_) => finish_staging(&db_writer, config, exit_code).await,
+                None => exit_code,
+            };
+
+            match (&config.metadata_collection, exit_code) {
+                (Some(metadata_collection), ExitCodes::Success) => write_metadata(&db_writer, metadata_collection, config, records_stored).await,
+                _ => exit_code,
+            }
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DatabaseError
+        }
+    }
+}
+
+// Record the source, time, number of records and tool version of a successful load, so readers of
+// the collection can tell how current it is
+async fn write_metadata<T>(db_writer: &DatabaseWriter<T>, metadata_collection: &str, config: &Config, records_stored: u64) -> ExitCodes
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    let metadata: Document = doc! {
+        "collection": &config.collection_name,
+        "source": config.source.description(),
+        "loadedAt": bson::DateTime::now(),
+        "recordsStored": records_stored as i64,
+        "version": env!("CARGO_PKG_VERSION"),
+    };
+
+    match db_writer.write_metadata(metadata_collection, metadata).await {
+        Ok(_) => {
+            let text: String = format!("Recorded the load of {} in {}", config.collection_name, metadata_collection);
+            info!(success = true, "{}", text);
+            ExitCodes::Success
+        }
+        Err(error) => {
+            let text = format!("Error: could not record the load in {}: {}", metadata_collection, report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DatabaseError
+        }
+    }
+}
+
+// Replace the collection with the staging collection after a successful load, or drop the
+// staging collection after a failed one, unless a checkpoint is kept to resume into it
+async fn finish_staging<T>(db_writer: &DatabaseWriter<T>, config: &Config, exit_code: ExitCodes) -> ExitCodes
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    if exit_code != ExitCodes::Success {
+        let text: String = format!("The collection {} has been left unchanged", config.collection_name);
+        report::error(&text);
+
+        if config.checkpoint.is_none() {
+            if let Err(error) = db_writer.drop_collection().await {
+                let text = format!("Warning: could not drop the staging collection: {}", error);
+                report::warning(&text);
+            }
+        }
+
+        return exit_code;
+    }
+
+    // The swap is a single renameCollection, so readers see the old or the new collection, never a mix
+    let topology: Topology = match db_writer.topology().await {
+        Ok(topology) => topology,
+        Err(error) => {
+            let text = format!("Error: could not detect the MongoDB deployment type: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DatabaseError;
+        }
+    };
+
+    match db_writer.rename_collection(&config.collection_name, topology).await {
+        Ok(_) => {
+            let text: String = format!("Replaced {} with the loaded records ({})", config.collection_name, topology);
+            info!(success = true, "{}", text);
+            exit_code
+        }
+        Err(error) => {
+            let text = format!("Error: could not replace the collection with the staging collection: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DatabaseError
+        }
+    }
+}
+
+async fn download_and_store<D>(
+    db_writer: &mut DatabaseWriter<OutputRecord<D>>,
+    config: &Config,
+    enrichment: Option<&Enrichment>,
+    client: &RunClient,
+) -> ExitCodes
+where
+    D: Model,
+{
+    // Create a new DownloadInfo struct
+    let mut download_info: DownloadInfo<D> = DownloadInfo::with_capacity(config.channel_capacity);
+
+    // Time the run for the throughput
+    let start: Instant = Instant::now();
+
+    // Gather statistics about the records
+    let mut summary: Summary = Summary::for_config(config);
+
+    // Keep a random sample of the records to check after the run
+    let mut sample: RecordSample = RecordSample::new(config.validate_sample);
+
+    // Load the checkpoint to resume from, if requested
+    let resume: Option<SavedCheckpoint> = match (&config.checkpoint, config.resume) {
+        (Some(path), true) => match Checkpoint::load(path) {
+            Ok(resume) => resume,
+            Err(error) => {
+                let text = format!("Error: {}", report::with_causes(&error));
+                report::error(&text);
+                return ExitCodes::CheckpointError;
+            }
+        },
+        _ => None,
+    };
+
+    // The chunk boundaries must match for the checkpoint to be meaningful
+    if let Some(saved) = &resume {
+        if saved.chunk_size != db_writer.chunk_size() {
+            let text = format!(
+                "Error: the checkpoint was written with a chunk size of {}, not {}",
+                saved.chunk_size,
+                db_writer.chunk_size()
+            );
+            report::error(&text);
+            return ExitCodes::CheckpointError;
+        }
+    }
+
+    // Skip the records in the chunks that were already inserted
+    let next_chunk: u64 = resume.as_ref().map(|saved| saved.next_chunk).unwrap_or_default();
+    let skip_records: u64 = resume.as_ref().map(SavedCheckpoint::records).unwrap_or_default();
+
+    // Record each inserted chunk in the checkpoint file
+    let checkpoint: Option<Arc<Checkpoint>> = config
+        .checkpoint
+        .as_ref()
+        .map(|path| Arc::new(Checkpoint::new(path, db_writer.chunk_size(), next_chunk)));
+
+    if let Some(checkpoint) = &checkpoint {
+        db_writer.set_checkpoint(checkpoint.clone(), next_chunk);
+    }
+
+    // Apply the source settings
+    configure_download(&mut download_info, config);
+
+    // Open the secondary sink for filtered out records
+    let mut filtered_out: Option<FilteredOutSink<OutputRecord<D>>> = match open_filtered_out(config, client).await {
+        Ok(filtered_out) => filtered_out,
+        Err(error_code) => return error_code,
+    };
+
+    // Show the progress of the download and the inserts that follow it
+    let mut progress: RunProgress = RunProgress::new(config.progress_mode, config.verbose_progress, true);
+
+    // Start reading the records from the source
+    let download_result = start_download(&mut download_info, &config.source).await;
+
+    // Exit code
+    let mut exit_code: ExitCodes = match download_result {
+        Ok(join_handle) => {
+            match &resume {
+                // Resuming, the collection and indexes are already in place
+                Some(saved) => {
+                    let text: String = format!(
+                        "Resuming from chunk {}, skipping {} records already inserted",
+                        saved.next_chunk,
+                        saved.records()
+                    );
+                    info!("{}", text);
+                }
+                // Starting afresh, drop the collection unless upserting and create the indexes
+                None => {
+                    if let Err(error_code) = prepare_collection(db_writer, &index_fields(config), stored_shard_key(config).as_ref(), config.upsert).await {
+                        return error_code;
+                    }
+                }
+            }
+
+            // Handle the download
+            let record_options: RecordOptions<D> = RecordOptions {
+                skip_records,
+                output_options: output_options(config),
+                age_reference_year: age_reference_year(config),
+                deduplicator: deduplicator(config),
+                max_field_length: config.max_field_length,
+                pad_icao24: config.pad_icao24,
+                limit: config.limit,
+                countries: countries(config),
+                filtered_out: filtered_out.as_mut(),
+                enrichment,
+            };
+
+            if let Err(error) = handle_download(&mut download_info, db_writer, &mut summary, &mut sample, record_options, &mut progress).await {
+                let text = format!("Error: {}", report::with_causes(&error));
+                report::error(&text);
+                return ExitCodes::DatabaseError;
+            }
+
+            // Wait for the task to finish
+            wait_for_download(join_handle, summary.limit.is_some()).await
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    // Report the skipped records
+    summary.record_errors = config.skips_bad_records().then(|| download_info.record_errors());
+    summary.recovered_records = config.quote_fallback.then(|| download_info.recovered_records());
+
+    // Check the whole download was read and matches its checksum, unless it was stopped at the limit
+    if exit_code == ExitCodes::Success && summary.limit.is_none() {
+        if let Err(error_code) = check_content_length(&download_info, config, &mut summary) {
+            exit_code = error_code;
+        }
+
+        if let Err(error_code) = check_checksum(&download_info, config) {
+            exit_code = error_code;
+        }
+    }
+
+    // Flush the filtered out records
+    if let Err(error_code) = finish_filtered_out(filtered_out.as_mut()).await {
+        exit_code = error_code;
+    }
+
+    // Don't commit the records held back from a failed download
+    if matches!(exit_code, ExitCodes::DownloadError) {
+        db_writer.discard_pending();
+    }
+
+    // Print that we are finishing writing the records
+    let text: String = match exit_code {
+        ExitCodes::Interrupted => format!("Interrupted, flushing {} pending batches", db_writer.pending_batches()),
+        _ => "Finishing inserting records".to_string(),
+    };
+    info!("{}", text);
+
+    // Finish writing the records
+    let mut channel = db_writer.finish().await;
+
+    // Show the percentage of the remaining inserts complete
+    progress.start_inserts();
+
+    // Keep the failed chunks to report once the progress indicator is finished
+    let mut insert_errors: Vec<DatabaseError> = Vec::new();
+
+    // Wait for the task to finish
+    while let Some(result) = channel.recv().await {
+        match result {
+            // Print the progress
+            Ok(percentage) => progress.set_insert_percent(percentage),
+            Err(error) => insert_errors.push(error),
+        }
+    }
+
+    // Finish the progress indicator
+    progress.finish();
+
+    match insert_errors.is_empty() {
+        true => {
+            // Print that we are finishing writing the records
+            let text: String = "Finished inserting records".to_string();
+            info!(success = true, "{}", text);
+        }
+        false => {
+            for error in &insert_errors {
+                let text = format!("Error: a chunk of records was not inserted: {}", report::with_causes(&error));
+                report::error(&text);
+            }
+
+            // Total the failures, so a run with many failed chunks can't be missed
+            let failed_documents: u64 = insert_errors.iter().map(DatabaseError::failed_documents).sum();
+            summary.add_insert_failures(insert_errors.len() as u64, failed_documents);
+
+            let text = format!(
+                "Error: {} chunks failed to insert, {} documents are missing from the collection",
+                insert_errors.len(),
+                failed_documents
+            );
+            report::error(&text);
+
+            // The checkpoint is kept below, so the failed chunks are inserted again on resume
+            if checkpoint.is_some() {
+                let text: String = "The checkpoint has been kept, rerun with --resume to retry the failed chunks".to_string();
+                report::error(&text);
+            }
+
+            exit_code = ExitCodes::DatabaseError;
+        }
+    }
+
+    // Make sure an interrupted load is never mistaken for a complete one
+    if exit_code == ExitCodes::Interrupted {
+        let text: String = match (&checkpoint, config.staging) {
+            (Some(_), _) => "Error: the load was interrupted, rerun with --resume to insert the rest of the records".to_string(),
+            (None, true) => "Error: the load was interrupted".to_string(),
+            (None, false) if config.upsert => format!(
+                "Error: the load was interrupted, only {} records were upserted into {}",
+                summary.records_stored, config.collection_name
+            ),
+            (None, false) => format!(
+                "Error: the load was interrupted, {} holds only the {} records inserted so far",
+                config.collection_name, summary.records_stored
+            ),
+        };
+        report::error(&text);
+    }
+
+    // Check that no records without an ICAO24 address reached the collection
+    if config.check_empty_icao24 {
+        match count_empty_icao24(db_writer, &config.output_options.stored_name("icao24")).await {
+            Ok(count) => summary.empty_icao24 = Some(count),
+            Err(error_code) => exit_code = error_code,
+        }
+    }
+
+    // Print the statistics
+    summary.set_duration(start.elapsed());
+    summary.print(config.summary_format);
+
+    // The load completed, so the checkpoint is no longer needed
+    if let (Some(checkpoint), ExitCodes::Success) = (&checkpoint, &exit_code) {
+        if let Err(error) = checkpoint.remove() {
+            let text = format!("Warning: could not remove the checkpoint file: {}", error);
+            report::warning(&text);
+        }
+    }
+
+    // Spot check the sampled records
+    if !sample.documents().is_empty() {
+        if let Err(error_code) = validate_sample(db_writer, &sample, &config.output_options.stored_name("icao24")).await {
+            exit_code = error_code;
+        }
+    }
+
+    // Prime the cache with a representative query
+    if let (Some(filter), ExitCodes::Success) = (&config.warmup_query, &exit_code) {
+        warmup(db_writer, filter).await;
+    }
+
+    // Check the queries the indexes were made for can use them
+    if let (true, ExitCodes::Success) = (config.verify_indexes, &exit_code) {
+        verify_indexes(db_writer, &index_fields(config)).await;
+    }
+
+    // Protect a good load from stray writes
+    if let (true, ExitCodes::Success) = (config.lock_after_load, &exit_code) {
+        match db_writer.lock_collection().await {
+            Ok(_) => {
+                let text: String = "Collection locked, inserts and updates will be rejected".to_string();
+                info!(success = true, "{}", text);
+            }
+            Err(error) => {
+                let text = format!("Error: could not lock the collection: {}", report::with_causes(&error));
+                report::error(&text);
+                exit_code = ExitCodes::DatabaseError;
+            }
+        }
+    }
+
+    exit_code
+}
+
+async fn download_and_export(path: &Path, config: &Config, enrichment: Option<&Enrichment>, client: &RunClient) -> ExitCodes {
+    // Create a new DownloadInfo struct
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::with_capacity(config.channel_capacity);
+
+    // Time the run for the throughput
+    let start: Instant = Instant::now();
+
+    // Gather statistics about the records
+    let mut summary: Summary = Summary::for_config(config);
+
+    // Sampling only applies to the database
+    let mut sample: RecordSample = RecordSample::new(0);
+
+    // Apply the source settings
+    configure_download(&mut download_info, config);
+
+    // Open the secondary sink for filtered out records
+    let mut filtered_out: Option<FilteredOutSink<OutputRecord<Aircraft>>> = match open_filtered_out(config, client).await {
+        Ok(filtered_out) => filtered_out,
+        Err(error_code) => return error_code,
+    };
+
+    // Start reading the records from the source
+    let join_handle = match start_download(&mut download_info, &config.source).await {
+        Ok(join_handle) => join_handle,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    // Nothing to skip when writing files
+    let record_options: RecordOptions<Aircraft> = RecordOptions {
+        skip_records: 0,
+        output_options: output_options(config),
+        age_reference_year: age_reference_year(config),
+        deduplicator: deduplicator(config),
+        max_field_length: config.max_field_length,
+        pad_icao24: config.pad_icao24,
+        limit: config.limit,
+        countries: countries(config),
+        filtered_out: filtered_out.as_mut(),
+        enrichment,
+    };
+
+    // Write the records to the output file(s)
+    let mut file_writer: JsonLinesWriter = JsonLinesWriter::new(path, config.max_file_records);
+    file_writer.set_keep_partial(config.keep_partial_output);
+
+    // Only the download to show, nothing is inserted afterwards
+    let mut progress: RunProgress = RunProgress::new(config.progress_mode, config.verbose_progress, false);
+
+    // A failed sink has already stopped the download and cleaned up its files
+    if let Err(error) = handle_download(&mut download_info, &mut file_writer, &mut summary, &mut sample, record_options, &mut progress).await {
+        let text = format!("Error: {}", report::with_causes(&error));
+        report::error(&text);
+        report_partial_output(&file_writer);
+        return ExitCodes::OutputError;
+    }
+
+    // Wait for the task to finish
+    let mut exit_code: ExitCodes = wait_for_download(join_handle, summary.limit.is_some()).await;
+
+    // Report the skipped records
+    summary.record_errors = config.skips_bad_records().then(|| download_info.record_errors());
+    summary.recovered_records = config.quote_fallback.then(|| download_info.recovered_records());
+
+    // Check the whole download was read and matches its checksum, unless it was stopped at the limit
+    if exit_code == ExitCodes::Success && summary.limit.is_none() {
+        if let Err(error_code) = check_content_length(&download_info, config, &mut summary) {
+            exit_code = error_code;
+        }
+
+        if let Err(error_code) = check_checksum(&download_info, config) {
+            exit_code = error_code;
+        }
+    }
+
+    // Flush the filtered out records
+    if let Err(error_code) = finish_filtered_out(filtered_out.as_mut()).await {
+        exit_code = error_code;
+    }
+
+    // Flush the last file, cleaning up the files if it can't be written
+    if let Err(error) = file_writer.finish().await {
+        let text = format!("Error: {}", error);
+        report::error(&text);
+
+        abort_sinks(error.into(), &mut file_writer, filtered_out.as_mut()).await;
+        report_partial_output(&file_writer);
+        return ExitCodes::OutputError;
+    }
+
+    // Print where the records were written
+    let text: String = format!(
+        "Wrote {} records to {} file(s), starting with {}",
+        file_writer.records(),
+        file_writer.files().len(),
+        file_writer.files().first().map(|file| file.display().to_string()).unwrap_or_default()
+    );
+    info!(success = true, "{}", text);
+
+    // Make sure an interrupted export is never mistaken for a complete one
+    if exit_code == ExitCodes::Interrupted {
+        let text: String = "Error: the export was interrupted, the files hold only the records read so far".to_string();
+        report::error(&text);
+    }
+
+    // Print the statistics
+    summary.set_duration(start.elapsed());
+    summary.print(config.summary_format);
+
+    exit_code
+}
+
+async fn download_distinct(config: &Config, enrichment: Option<&Enrichment>) -> ExitCodes {
+    // Create a new DownloadInfo struct
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::with_capacity(config.channel_capacity);
+
+    // The statistics aren't printed, only the values
+    let mut summary: Summary = Summary::for_config(config);
+
+    // Sampling only applies to the database
+    let mut sample: RecordSample = RecordSample::new(0);
+
+    // Apply the source settings
+    configure_download(&mut download_info, config);
+
+    // Start reading the records from the source
+    let join_handle = match start_download(&mut download_info, &config.source).await {
+        Ok(join_handle) => join_handle,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    // Records are processed as for a load, so enrichment and deduplication are reflected in the values
+    let record_options: RecordOptions<Aircraft> = RecordOptions {
+        skip_records: 0,
+        output_options: output_options(config),
+        age_reference_year: age_reference_year(config),
+        deduplicator: deduplicator(config),
+        max_field_length: config.max_field_length,
+        pad_icao24: config.pad_icao24,
+        limit: config.limit,
+        countries: countries(config),
+        filtered_out: None,
+        enrichment,
+    };
+
+    // Collect the values of the field
+    let mut distinct: DistinctValues = DistinctValues::new(&config.distinct_field, config.distinct_limit);
+
+    // Only the download to show, nothing is inserted afterwards
+    let mut progress: RunProgress = RunProgress::new(config.progress_mode, config.verbose_progress, false);
+
+    if let Err(error) = handle_download(&mut download_info, &mut distinct, &mut summary, &mut sample, record_options, &mut progress).await {
+        let text = format!("Error: {}", report::with_causes(&error));
+        report::error(&text);
+        return ExitCodes::OutputError;
+    }
+
+    // Wait for the task to finish
+    let exit_code: ExitCodes = wait_for_download(join_handle, summary.limit.is_some()).await;
+
+    if exit_code != ExitCodes::Success {
+        return exit_code;
+    }
+
+    // Print the values one per line so they can be piped elsewhere
+    let text: String = format!("{} distinct values of {}:", distinct.values().len(), config.distinct_field);
+    println!("{}", text.yellow().bold());
+
+    for value in distinct.values() {
+        println!("{}", value);
+    }
+
+    exit_code
+}
+
+async fn dry_run(config: &Config, enrichment: Option<&Enrichment>) -> ExitCodes {
+    // Create a new DownloadInfo struct
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::with_capacity(config.channel_capacity);
+
+    // Time the run for the throughput
+    let start: Instant = Instant::now();
+
+    // Gather statistics about the records
+    let mut summary: Summary = Summary::for_config(config);
+
+    // Sampling only applies to the database
+    let mut sample: RecordSample = RecordSample::new(0);
+
+    // Apply the source settings
+    configure_download(&mut download_info, config);
+
+    // Print that nothing will be written
+    let text: String = "Dry run, the records are parsed and counted but not stored".to_string();
+    info!(notice = true, "{}", text);
+
+    // Start reading the records from the source
+    let join_handle = match start_download(&mut download_info, &config.source).await {
+        Ok(join_handle) => join_handle,
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            return ExitCodes::DownloadError;
+        }
+    };
+
+    // Records are processed as for a load, but the filtered out ones aren't written either
+    let record_options: RecordOptions<Aircraft> = RecordOptions {
+        skip_records: 0,
+        output_options: output_options(config),
+        age_reference_year: age_reference_year(config),
+        deduplicator: deduplicator(config),
+        max_field_length: config.max_field_length,
+        pad_icao24: config.pad_icao24,
+        limit: config.limit,
+        countries: countries(config),
+        filtered_out: None,
+        enrichment,
+    };
+
+    // Only the download to show, nothing is inserted afterwards
+    let mut progress: RunProgress = RunProgress::new(config.progress_mode, config.verbose_progress, false);
+
+    if let Err(error) = handle_download(&mut download_info, &mut DryRunSink, &mut summary, &mut sample, record_options, &mut progress).await {
+        let text = format!("Error: {}", report::with_causes(&error));
+        report::error(&text);
+        return ExitCodes::OutputError;
+    }
+
+    // Wait for the task to finish
+    let mut exit_code: ExitCodes = wait_for_download(join_handle, summary.limit.is_some()).await;
+
+    // Report the skipped records
+    summary.record_errors = config.skips_bad_records().then(|| download_info.record_errors());
+    summary.recovered_records = config.quote_fallback.then(|| download_info.recovered_records());
+
+    // Check the whole download was read and matches its checksum, unless it was stopped at the limit
+    if exit_code == ExitCodes::Success && summary.limit.is_none() {
+        if let Err(error_code) = check_content_length(&download_info, config, &mut summary) {
+            exit_code = error_code;
+        }
+
+        if let Err(error_code) = check_checksum(&download_info, config) {
+            exit_code = error_code;
+        }
+    }
+
+    // Print the statistics
+    summary.set_duration(start.elapsed());
+    summary.print(config.summary_format);
+
+    exit_code
+}
+
+// Apply the settings for reading the source
+fn configure_download<D>(download_info: &mut DownloadInfo<D>, config: &Config)
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    // Read any renamed CSV columns as their model fields
+    download_info.set_column_renames(config.column_renames.clone());
+
+    // Read the CSV with the requested dialect
+    download_info.set_csv_dialect(config.csv_dialect);
+
+    // Keep the source lines if requested
+    download_info.set_keep_raw(config.keep_raw);
+
+    // Skip malformed records up to the limit
+    download_info.set_on_error(config.on_error);
+    download_info.set_max_errors(config.max_errors);
+
+    // Read records that fail to parse again without quoting
+    download_info.set_quote_fallback(config.quote_fallback);
+
+    // Split the download across connections
+    download_info.set_download_connections(config.download_connections);
+
+    // Retry a request that fails with a transient error
+    download_info.set_max_attempts(config.max_attempts);
+
+    // Resume an interrupted download
+    download_info.set_max_reconnects(config.max_reconnects);
+
+    // Hash the source to check or report its checksum
+    download_info.set_checksum_algorithm(config.checksum_algorithm);
+
+    // Fail the download if it stalls
+    download_info.set_stall_timeout(config.stall_timeout);
+    download_info.set_http_timeout(config.http_timeout);
+
+    // Sign the requests for a mirror that needs an HMAC signature
+    download_info.set_request_signer(config.request_signer.clone());
+
+    // Send the requests through the proxy, if there is one
+    download_info.set_proxy(config.proxy.clone());
+
+    // Add the headers the server asks for, such as a token or a contact address
+    download_info.set_request_headers(config.request_headers.clone());
+
+    // Show the progress of parallel range downloads
+    download_info.set_progress_mode(config.progress_mode);
+
+    // Report the progress to the library user's callback as well, if there is one
+    if let Some(progress_callback) = &config.progress_callback {
+        download_info.set_progress_callback(progress_callback.clone());
+    }
+
+    // Bound the memory held by the records read but not yet stored
+    if let Some(memory_budget) = config.memory_budget {
+        download_info.set_memory_budget(MemoryBudget::new(memory_budget));
+    }
+
+    // Let the operator pause and resume the download with SIGUSR1
+    #[cfg(unix)]
+    match pause::toggle_on_sigusr1() {
+        Ok(pause) => download_info.set_pause(pause),
+        Err(error) => {
+            let text = format!("Warning: SIGUSR1 won't pause the download: {}", error);
+            report::warning(&text);
+        }
+    }
+}
+
+async fn start_download<D>(download_info: &mut DownloadInfo<D>, source: &Source) -> Result<JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    match source {
+        Source::Url(url) => {
+            // Print that we are downloading the file
+            let text: String = format!("Downloading file from {}", url);
+            info!("{}", text);
+
+            // Download the file
+            download_info.download(url).await
+        }
+        Source::S3(url) => {
+            // Print that we are downloading the object
+            let text: String = format!("Downloading object from {}", url);
+            info!("{}", text);
+
+            // Download the object
+            download_info.download_s3(url).await
+        }
+        Source::File(path) => {
+            // Print that we are reading the file
+            let text: String = format!("Reading records from {}", path.display());
+            info!("{}", text);
+
+            // Read the records from the file, using its size for the progress
+            download_info.download_file(path).await
+        }
+        Source::Stdin => {
+            // Print that we are reading from stdin
+            let text: String = "Reading records from stdin".to_string();
+            info!("{}", text);
+
+            // Read the records from stdin
+            download_info.download_from_reader(tokio::io::stdin()).await
+        }
+    }
+}
+
+async fn open_filtered_out<D>(config: &Config, client: &RunClient) -> Result<Option<FilteredOutSink<OutputRecord<D>>>, ExitCodes>
+where
+    D: Model,
+{
+    let target: &str = match &config.filtered_out {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+
+    match target.strip_prefix(FILTERED_OUT_COLLECTION_PREFIX) {
+        // A collection in the same database, replaced on each run
+        Some(collection_name) => {
+            let result = match client.writer::<OutputRecord<D>>(config, &config.database_name, collection_name).await {
+                Ok(db_writer) => db_writer.drop_collection().await.map(|_| db_writer),
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(db_writer) => Ok(Some(FilteredOutSink::Collection(db_writer))),
+                Err(error) => {
+                    let text = format!("Error: could not open the filtered out collection: {}", report::with_causes(&error));
+                    report::error(&text);
+                    Err(ExitCodes::DatabaseError)
+                }
+            }
+        }
+        // A JSON lines file, overwritten on each run
+        None => {
+            let mut file_writer: JsonLinesWriter = JsonLinesWriter::new(Path::new(target), None);
+            file_writer.set_keep_partial(config.keep_partial_output);
+
+            Ok(Some(FilteredOutSink::File(file_writer)))
+        }
+    }
+}
+
+// Say what became of the files written before an export failed
+fn report_partial_output(file_writer: &JsonLinesWriter) {
+    if file_writer.files().is_empty() {
+        return;
+    }
+
+    let text: String = match file_writer.keep_partial() {
+        true => format!("Kept the {} incomplete output file(s) with a .{} suffix", file_writer.files().len(), PARTIAL_SUFFIX),
+        false => format!("Removed the {} incomplete output file(s)", file_writer.files().len()),
+    };
+    info!("{}", text);
+}
+
+async fn finish_filtered_out<T>(filtered_out: Option<&mut FilteredOutSink<T>>) -> Result<(), ExitCodes>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    if let Some(filtered_out) = filtered_out {
+        if let Err(error) = filtered_out.finish().await {
+            let text = format!("Error: could not write the filtered out records: {}", report::with_causes(&error));
+            report::error(&text);
+            return Err(ExitCodes::OutputError);
+        }
+    }
+
+    Ok(())
+}
+
+// Compare the bytes read with the content length, a mismatch means a truncated or padded download
+fn check_content_length<D>(download_info: &DownloadInfo<D>, config: &Config, summary: &mut Summary) -> Result<(), ExitCodes>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    let bytes_read: u64 = download_info.bytes_read();
+    summary.bytes_read = Some(bytes_read);
+
+    // Nothing to compare with when the length isn't known, e.g. for stdin
+    if download_info.content_length == 0 {
+        return Ok(());
+    }
+
+    summary.content_length = Some(download_info.content_length);
+
+    if bytes_read == download_info.content_length {
+        return Ok(());
+    }
+
+    match config.strict {
+        true => {
+            let text = format!("Error: read {} bytes but the content length is {}", bytes_read, download_info.content_length);
+            report::error(&text);
+            Err(ExitCodes::DownloadError)
+        }
+        false => {
+            let text = format!("Warning: read {} bytes but the content length is {}", bytes_read, download_info.content_length);
+            report::warning(&text);
+            Ok(())
+        }
+    }
+}
+
+// Print the digest of the source and compare it with the expected one
+async fn fetch_expected_checksum(checksum_url: &str, config: &Config) -> Result<String, ExitCodes> {
+    let algorithm: ChecksumAlgorithm = config.checksum_algorithm.unwrap_or_default();
+
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+    download_info.set_max_attempts(config.max_attempts);
+    download_info.set_request_signer(config.request_signer.clone());
+    download_info.set_proxy(config.proxy.clone());
+    download_info.set_request_headers(config.request_headers.clone());
+    download_info.set_http_timeout(config.http_timeout);
+
+    let contents: String = match download_info.fetch_text(checksum_url).await {
+        Ok(contents) => contents,
+        Err(error) => {
+            let text = format!("Error: could not read the checksum file {}: {}", checksum_url, report::with_causes(&error));
+            report::error(&text);
+            return Err(ExitCodes::DownloadError);
+        }
+    };
+
+    match parse_sidecar(&contents, source_file_name(&config.source), algorithm) {
+        Ok(expected_checksum) => {
+            let text = format!("Expected {}: {}", algorithm.name(), expected_checksum);
+            info!("{}", text);
+            Ok(expected_checksum)
+        }
+        Err(error) => {
+            let text = format!("Error: {}: {}", checksum_url, error);
+            report::error(&text);
+            Err(ExitCodes::DownloadError)
+        }
+    }
+}
+
+// The file name at the end of the source, used to find its line in a checksum file
+fn source_file_name(source: &Source) -> Option<&str> {
+    match source {
+        Source::Url(url) | Source::S3(url) => url.split(['?', '#']).next()?.rsplit('/').next().filter(|name| !name.is_empty()),
+        Source::File(path) => path.file_name()?.to_str(),
+        Source::Stdin => None,
+    }
+}
+
+fn check_checksum<D>(download_info: &DownloadInfo<D>, config: &Config) -> Result<(), ExitCodes>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    let (Some(algorithm), Some(checksum)) = (config.checksum_algorithm, download_info.checksum()) else {
+        return Ok(());
+    };
+
+    let text = format!("{}: {}", algorithm.name(), checksum);
+    info!("{}", text);
+
+    match &config.expected_checksum {
+        Some(expected_checksum) if !expected_checksum.eq_ignore_ascii_case(&checksum) => {
+            let text = format!("Error: the {} checksum is {} but {} was expected", algorithm.name(), checksum, expected_checksum);
+            report::error(&text);
+            Err(ExitCodes::DownloadError)
+        }
+        _ => Ok(()),
+    }
+}
+
+// The next record, or None once the source has ended or Ctrl-C has been pressed
+//
+// If the reader is waiting for room in the memory budget, the sink's partly filled batch is sent
+// on, as the records held in it may be what is using up the budget
+async fn next_record<D, S>(
+    rx_channel: &mut Receiver<RecordInfo<D>>,
+    memory_budget: Option<&MemoryBudget>,
+    sink: &mut S,
+) -> Option<RecordInfo<D>>
+where
+    S: RecordSink<OutputRecord<D>>,
+{
+    loop {
+        tokio::select! {
+            biased;
+            _ = interrupt::interrupted() => return None,
+            record_info = rx_channel.recv() => return record_info,
+            _ = memory_exhausted(memory_budget) => sink.flush_partial().await,
+        }
+    }
+}
+
+// Return once the reader is waiting for memory, never if there is no budget
+async fn memory_exhausted(memory_budget: Option<&MemoryBudget>) {
+    match memory_budget {
+        Some(memory_budget) => memory_budget.exhausted().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn wait_for_download<D>(join_handle: JoinHandle<Result<(), DownloadError<D>>>, stopped_at_limit: bool) -> ExitCodes
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    // The records already read have been handled, so stop reading the rest
+    if interrupt::is_interrupted() {
+        join_handle.abort();
+        return ExitCodes::Interrupted;
+    }
+
+    // The channel was closed at the limit, so the reader would only fail to send the next record
+    if stopped_at_limit {
+        join_handle.abort();
+        let text: String = "Download stopped at the limit".to_string();
+        info!(success = true, "{}", text);
+        return ExitCodes::Success;
+    }
+
+    // Wait for the task to finish
+    match join_handle.await {
+        Ok(Ok(_)) => {
+            let text: String = "Download complete".to_string();
+            info!(success = true, "{}", text);
+            ExitCodes::Success
+        }
+        Ok(Err(error)) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::DownloadError
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            ExitCodes::JoinError
+        }
+    }
+}
+
+async fn prepare_collection<T>(
+    db_writer: &DatabaseWriter<T>,
+    index_fields: &[IndexSpec],
+    shard_key: Option<&ShardKey>,
+    upsert: bool,
+) -> Result<(), ExitCodes>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    // Check a shard key can be used before the collection is dropped
+    if shard_key.is_some() {
+        match db_writer.topology().await {
+            Ok(Topology::Sharded) => {}
+            Ok(topology) => {
+                let text = format!("Error: --shard-key needs a connection to a mongos, the server is a {}", topology);
+                report::error(&text);
+                return Err(ExitCodes::ConfigError);
+            }
+            Err(error) => {
+                let text = format!("Error: could not detect the MongoDB deployment type: {}", report::with_causes(&error));
+                report::error(&text);
+                return Err(ExitCodes::DatabaseError);
+            }
+        }
+    }
+
+    match upsert {
+        // Keep the collection, so readers never see it empty
+        true => {
+            let text: String = "Source found, updating the collection in place".to_string();
+            info!("{}", text);
+        }
+        false => {
+            // Print that we are dropping the collection
+            let text: String = "Source found, dropping collection".to_string();
+            info!("{}", text);
+
+            // File found successfully, drop the collection
+            match db_writer.drop_collection().await {
+                Ok(_) => {
+                    let text: String = "Collection dropped".to_string();
+                    info!(success = true, "{}", text);
+                }
+                Err(error) => {
+                    let text = format!("Error: {}", report::with_causes(&error));
+                    report::error(&text);
+                    return Err(ExitCodes::DatabaseError);
+                }
+            }
+        }
+    }
+
+    // Create the collection before the indexes, an existing collection is not an error
+    if let Err(error) = db_writer.create_collection().await {
+        let text = format!("Error: could not create the collection: {}", report::with_causes(&error));
+        report::error(&text);
+        return Err(ExitCodes::DatabaseError);
+    }
+
+    // Shard the empty collection, so the load is spread across the shards from the start
+    if let Some(shard_key) = shard_key {
+        match db_writer.shard_collection(shard_key).await {
+            Ok(_) => {
+                let text: String = format!("Collection sharded on {}", shard_key.field);
+                info!(success = true, "{}", text);
+            }
+            Err(error) => {
+                let text = format!("Error: could not shard the collection: {}", report::with_causes(&error));
+                report::error(&text);
+                return Err(ExitCodes::DatabaseError);
+            }
+        }
+    }
+
+    // Print that we are creating the indexes
+    let text: String = "Creating new indexes".to_string();
+    info!("{}", text);
+
+    // Create the indexes in parallel, reporting each one as it is checked
+    let mut index_failed: bool = false;
+
+    for (field, result) in db_writer.create_indexes(index_fields).await {
+        match result {
+            Ok(duration) => {
+                let text: String = format!("Index on {} created in {:.2?}", field, duration);
+                info!(success = true, "{}", text);
+            }
+            Err(error) => {
+                let text = format!("Error creating index on {}: {}", field, error);
+                report::error(&text);
+                index_failed = true;
+            }
+        }
+    }
+
+    match index_failed {
+        true => Err(ExitCodes::DatabaseError),
+        false => Ok(()),
+    }
+}
+
+// Run the warmup query, a failure is only a warning as the load itself succeeded
+async fn warmup<T>(db_writer: &DatabaseWriter<T>, filter: &Document)
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    let start: Instant = Instant::now();
+
+    match db_writer.count_documents(filter.clone()).await {
+        Ok(count) => {
+            let text: String = format!("Warmup query matched {} documents in {:.2?}", count, start.elapsed());
+            info!(success = true, "{}", text);
+        }
+        Err(error) => {
+            let text = format!("Warning: the warmup query failed: {}", error);
+            report::warning(&text);
+        }
+    }
+}
+
+// Explain an equality query on the first field of each index, warning if the planner would scan the
+// collection instead, a failure is only a warning as the load itself succeeded
+async fn verify_indexes<T>(db_writer: &DatabaseWriter<T>, indexes: &[IndexSpec])
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    for index in indexes {
+        let Some(field) = index.fields().next() else {
+            continue;
+        };
+
+        // Query a value that is stored, so the planner can't answer from an empty range
+        let value: Bson = match db_writer.sample_value(field).await {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                let text: String = format!("No stored document has {}, the index on {} was not checked", field, index);
+                info!(notice = true, "{}", text);
+                continue;
+            }
+            Err(error) => {
+                let text = format!("Warning: could not read a value of {} to check its index: {}", field, error);
+                report::warning(&text);
+                continue;
+            }
+        };
+
+        match db_writer.explain_find(doc! { field: value }).await {
+            Ok(plan) if plan.uses_index() => {
+                let text: String = format!("A query on {} uses the index on {}, plan {}", field, index, plan);
+                info!(success = true, "{}", text);
+            }
+            Ok(plan) => {
+                let text = format!("Warning: a query on {} does not use the index on {}, plan {}", field, index, plan);
+                report::warning(&text);
+            }
+            Err(error) => {
+                let text = format!("Warning: could not explain a query on {}: {}", field, error);
+                report::warning(&text);
+            }
+        }
+    }
+}
+
+async fn count_empty_icao24<T>(db_writer: &DatabaseWriter<T>, icao24_field: &str) -> Result<u64, ExitCodes>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    // Matches empty strings, and with null both null and missing fields
+    let filter = doc! { "$or": [{ icao24_field: "" }, { icao24_field: null }] };
+
+    match db_writer.count_documents(filter).await {
+        Ok(count) => {
+            // Empty records are filtered before insert, so any found point to a bug or another writer
+            if count > 0 {
+                let text = format!("Warning: {} stored documents have an empty or missing icao24", count);
+                report::warning(&text);
+            }
+
+            Ok(count)
+        }
+        Err(error) => {
+            let text = format!("Error: {}", report::with_causes(&error));
+            report::error(&text);
+            Err(ExitCodes::DatabaseError)
+        }
+    }
+}
+
+async fn validate_sample<T>(db_writer: &DatabaseWriter<T>, sample: &RecordSample, icao24_field: &str) -> Result<(), ExitCodes>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    // Print that we are validating the sample
+    let text: String = format!("Validating {} sampled records", sample.documents().len());
+    info!("{}", text);
+
+    let mut failures: usize = 0;
+
+    for sent in sample.documents() {
+        let icao24: &str = sent.get_str(icao24_field).unwrap_or_default();
+
+        // Read the record back using its ICAO24 address
+        let stored = match db_writer.find_documents(doc! { icao24_field: icao24 }).await {
+            Ok(stored) => stored,
+            Err(error) => {
+                let text = format!("Error: {}", report::with_causes(&error));
+                report::error(&text);
+                return Err(ExitCodes::DatabaseError);
+            }
+        };
+
+        match check_sample(sent, &stored) {
+            SampleCheck::Match => {}
+            SampleCheck::Missing => {
+                let text = format!("Sampled record {} was not found", icao24);
+                report::warning(&text);
+                failures += 1;
+            }
+            SampleCheck::Mismatch => {
+                let text = format!("Sampled record {} does not match what was sent", icao24);
+                report::warning(&text);
+                failures += 1;
+            }
+        }
+    }
+
+    match failures {
+        0 => {
+            let text: String = format!("Sample validation passed ({} records)", sample.documents().len());
+            info!(success = true, "{}", text);
+            Ok(())
+        }
+        _ => {
+            let text = format!("Sample validation failed for {} of {} records", failures, sample.documents().len());
+            report::error(&text);
+            Err(ExitCodes::ValidationError)
+        }
+    }
+}
+
+// The indexes on the stored names of the fields, including any optional ones
+fn index_fields(config: &Config) -> Vec<IndexSpec> {
+    let mut index_fields: Vec<&str> = config.index_fields.iter().map(String::as_str).collect();
+
+    if config.index_age_years {
+        index_fields.push(AGE_YEARS_FIELD);
+    }
+
+    // The upsert key may already be indexed with --index
+    if config.upsert && !index_fields.contains(&UPSERT_KEY) {
+        index_fields.push(UPSERT_KEY);
+    }
+
+    index_fields.into_iter().map(|field| IndexSpec::ascending(&config.output_options.stored_name(field))).collect()
+}
+
+// The shard key under the stored name of its field
+fn stored_shard_key(config: &Config) -> Option<ShardKey> {
+    config.shard_key.as_ref().map(|shard_key| ShardKey {
+        field: config.output_options.stored_name(&shard_key.field),
+        hashed: shard_key.hashed,
+    })
+}
+
+// Deduplicate on the key fields, if any were given
+fn deduplicator(config: &Config) -> Option<Deduplicator> {
+    (!config.dedup_key.is_empty()).then(|| Deduplicator::new(config.dedup_key.clone()))
+}
+
+// The current year if ages are stored
+fn age_reference_year(config: &Config) -> Option<i32> {
+    config.age_years.then(|| chrono::Utc::now().year())
+}
+
+// The countries given with --country, in the form they are compared in
+fn countries(config: &Config) -> Vec<String> {
+    config.countries.iter().map(|country| normalise_country(country)).collect()
+}
+
+// The options applied to each record as it is serialized
+fn output_options(config: &Config) -> Arc<OutputOptions> {
+    Arc::new(config.output_options.clone())
+}
+
+// Settings applied to each record before it is sent to the sink
+struct RecordOptions<'a, D>
+where
+    D: Model,
+{
+    // Records already inserted before a checkpoint
+    skip_records: u64,
+    output_options: Arc<OutputOptions>,
+    // The year ages are calculated from, if ages are stored
+    age_reference_year: Option<i32>,
+    deduplicator: Option<Deduplicator>,
+    // Strings longer than this many characters are cut
+    max_field_length: Option<usize>,
+    // Left-pad short ICAO24 addresses, skipping those that can't be valid
+    pad_icao24: bool,
+    // Stop after this many records have been stored
+    limit: Option<u64>,
+    // Only the records from these countries are stored, normalised, all of them if empty
+    countries: Vec<String>,
+    filtered_out: Option<&'a mut FilteredOutSink<OutputRecord<D>>>,
+    enrichment: Option<&'a Enrichment>,
+}
+
+// Send a filtered out record to the secondary sink, if there is one
+async fn filter_out<D>(record_info: RecordInfo<D>, record_options: &mut RecordOptions<'_, D>, summary: &mut Summary) -> Result<(), SinkError>
+where
+    D: Model,
+{
+    if let (Some(filtered_out), Some(count)) = (record_options.filtered_out.as_deref_mut(), summary.filtered_out.as_mut()) {
+        let output_record = OutputRecord::new(record_info.record, record_options.output_options.clone()).with_raw(record_info.raw);
+        filtered_out.add_record(output_record).await?;
+        *count += 1;
+    }
+
+    Ok(())
+}
+
+// Clean up the partial output of every sink after one of them failed, returning the error that stopped the run
+async fn abort_sinks<D, S>(error: SinkError, sink: &mut S, filtered_out: Option<&mut FilteredOutSink<OutputRecord<D>>>) -> SinkError
+where
+    D: Model,
+    S: RecordSink<OutputRecord<D>>,
+{
+    if let Err(abort_error) = sink.abort().await {
+        let text = format!("Warning: could not clean up the incomplete output: {}", abort_error);
+        report::warning(&text);
+    }
+
+    if let Some(filtered_out) = filtered_out {
+        if let Err(abort_error) = filtered_out.abort().await {
+            let text = format!("Warning: could not clean up the incomplete filtered out records: {}", abort_error);
+            report::warning(&text);
+        }
+    }
+
+    error
+}
+
+async fn handle_download<D, S>(
+    download_info: &mut DownloadInfo<D>,
+    sink: &mut S,
+    summary: &mut Summary,
+    sample: &mut RecordSample,
+    mut record_options: RecordOptions<'_, D>,
+    progress: &mut RunProgress,
+) -> Result<(), SinkError>
+where
+    D: Model,
+    S: RecordSink<OutputRecord<D>>,
+{
+    // Show the download, weighing it against the inserts by the records still to insert
+    progress.start_download(download_info.content_length, record_options.skip_records);
+
+    // Download the file, stopping early if Ctrl-C is pressed
+    // The budget shared with the reader of the source, if there is one
+    let memory_budget: Option<Arc<MemoryBudget>> = download_info.memory_budget();
+
+    while let Some(mut record_info) = next_record(&mut download_info.rx_channel, memory_budget.as_deref(), sink).await {
+        // Print the progress
+        progress.set_download_position(record_info.position);
+
+        summary.records_read += 1;
+        metrics().record_read(record_info.position);
+
+        // Cut overlong fields before anything else sees the record
+        if let Some(max_field_length) = record_options.max_field_length {
+            summary.add_truncated(record_info.record.truncate_fields(max_field_length));
+        }
+
+        // Skip the records the model doesn't store
+        if !record_info.record.filter() {
+            summary.add_filtered();
+
+            if let Err(error) = filter_out(record_info, &mut record_options, summary).await {
+                download_info.rx_channel.close();
+                progress.finish();
+                return Err(abort_sinks(error, sink, record_options.filtered_out).await);
+            }
+
+            continue;
+        }
+
+        // Skip the records from countries that weren't asked for
+        if !record_options.countries.is_empty() && !record_info.record.country_in(&record_options.countries) {
+            if let Some(skipped_country) = summary.skipped_country.as_mut() {
+                *skipped_country += 1;
+            }
+
+            if let Err(error) = filter_out(record_info, &mut record_options, summary).await {
+                download_info.rx_channel.close();
+                progress.finish();
+                return Err(abort_sinks(error, sink, record_options.filtered_out).await);
+            }
+
+            continue;
+        }
+
+        // Normalise the record, e.g. uppercasing the ICAO24
+        record_info.record = record_info.record.map();
+
+        // Pad short ICAO24 addresses to six digits, before deduplication so the padded form is the key
+        if record_options.pad_icao24 {
+            match record_info.record.pad_icao24() {
+                Icao24Padding::Unchanged => {}
+                Icao24Padding::Padded => {
+                    if let Some(padded_icao24) = summary.padded_icao24.as_mut() {
+                        *padded_icao24 += 1;
+                    }
+                }
+                Icao24Padding::Invalid => {
+                    if let Some(invalid_icao24) = summary.invalid_icao24.as_mut() {
+                        *invalid_icao24 += 1;
+                    }
+
+                    if let Err(error) = filter_out(record_info, &mut record_options, summary).await {
+                        download_info.rx_channel.close();
+                        progress.finish();
+                        return Err(abort_sinks(error, sink, record_options.filtered_out).await);
+                    }
+
+                    continue;
+                }
+            }
+        }
+
+        // Drop repeated keys, before the checkpoint skip so the skipped count matches what was sent
+        if let (Some(deduplicator), Some(duplicates)) = (record_options.deduplicator.as_mut(), summary.duplicates.as_mut()) {
+            if deduplicator.is_duplicate(&record_info.record) {
+                *duplicates += 1;
+
+                if let Err(error) = filter_out(record_info, &mut record_options, summary).await {
+                    download_info.rx_channel.close();
+                    progress.finish();
+                    return Err(abort_sinks(error, sink, record_options.filtered_out).await);
+                }
+
+                continue;
+            }
+        }
+
+        // Skip the records that were inserted before the checkpoint
+        if record_options.skip_records > 0 {
+            record_options.skip_records -= 1;
+            continue;
+        }
+
+        // Override the manufacturer details from the lookup file
+        if let (Some(enrichment), Some(enriched)) = (record_options.enrichment, summary.enriched.as_mut()) {
+            if record_info.record.enrich(enrichment) {
+                *enriched += 1;
+            }
+        }
+
+        // Derive the age from the built date
+        if let Some(age_reference_year) = record_options.age_reference_year {
+            record_info.record.set_age_years(age_reference_year);
+        }
+
+        // Track how recent the data is
+        if let (Some(timestamp_range), Some(timestamp)) = (summary.timestamp.as_mut(), record_info.record.timestamp()) {
+            timestamp_range.update(timestamp);
+        }
+
+        if let (Some(built_range), Some(built)) = (summary.built.as_mut(), record_info.record.built_year()) {
+            built_range.update(&built.to_string());
+        }
+
+        // Wrap the record with the output options, holding its share of the memory budget until it is stored
+        let output_record = OutputRecord::new(record_info.record, record_options.output_options.clone())
+            .with_raw(record_info.raw)
+            .with_memory(record_info.memory);
+
+        // Offer the record to the validation sample
+        sample.offer(&output_record);
+
+        // Send the record to the sink, stopping the download if it fails
+        if let Err(error) = sink.add_record(output_record).await {
+            download_info.rx_channel.close();
+            progress.finish();
+            return Err(abort_sinks(error, sink, record_options.filtered_out).await);
+        }
+
+        summary.records_stored += 1;
+        metrics().record_stored();
+
+        // Stop reading once enough records have been stored, the reader is aborted by wait_for_download
+        if record_options.limit.is_some_and(|limit| summary.records_stored >= limit) {
+            summary.limit = record_options.limit;
+            download_info.rx_channel.close();
+            break;
+        }
+    }
+
+    // Finish the download phase, the overall indicator carries on through the inserts
+    progress.finish_download();
+
+    // Report how much of the memory budget was used
+    if let Some(memory_budget) = memory_budget {
+        summary.set_memory_use(memory_budget.peak() as u64, memory_budget.bytes() as u64);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write as _;
+
+    use tempfile::NamedTempFile;
+
+    use models::field_names;
+    use progress::ProgressMode;
+
+    // A file of records with the full aircraft header and only the icao24 filled in
+    fn aircraft_file(icao24s: &[&str]) -> NamedTempFile {
+        let header: Vec<String> = field_names::<Aircraft>().iter().map(|name| format!("'{}'", name)).collect();
+        let mut csv: String = format!("{}\r\n", header.join(","));
+
+        for icao24 in icao24s {
+            let fields: Vec<String> = field_names::<Aircraft>()
+                .iter()
+                .map(|name| match *name {
+                    "icao24" => format!("'{}'", icao24),
+                    _ => "''".to_string(),
+                })
+                .collect();
+
+            csv.push_str(&format!("{}\r\n", fields.join(",")));
+        }
+
+        let mut file: NamedTempFile = NamedTempFile::new().unwrap();
+        file.write_all(csv.as_bytes()).unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn the_run_summary_counts_the_records() {
+        let file: NamedTempFile = aircraft_file(&["4ca1fa", "", "a0b1c2"]);
+
+        let config: Config = Config {
+            source: Source::File(file.path().to_path_buf()),
+            dry_run: true,
+            progress_mode: ProgressMode::Plain,
+            ..Config::default()
+        };
+
+        let summary: RunSummary = run_with_summary(config).await;
+
+        assert!(summary.succeeded());
+        assert_eq!((summary.records_read, summary.records_stored), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn a_limit_that_fills_the_last_chunk_sends_no_empty_chunk() {
+        let file: NamedTempFile = aircraft_file(&["4ca1fa", "a0b1c2", "3c6444"]);
+
+        let config: Config = Config {
+            source: Source::File(file.path().to_path_buf()),
+            limit: Some(2),
+            progress_mode: ProgressMode::Plain,
+            ..Config::default()
+        };
+
+        let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+        let join_handle = start_download(&mut download_info, &config.source).await.unwrap();
+
+        // A chunk of two, so the limit is reached with the chunk full
+        let mut db_writer: DatabaseWriter<OutputRecord<Aircraft>> = DatabaseWriter::unreachable(2).await;
+        let mut summary: Summary = Summary::for_config(&config);
+
+        let record_options: RecordOptions<Aircraft> = RecordOptions {
+            skip_records: 0,
+            output_options: output_options(&config),
+            age_reference_year: None,
+            deduplicator: None,
+            max_field_length: None,
+            pad_icao24: false,
+            limit: config.limit,
+            countries: Vec::new(),
+            filtered_out: None,
+            enrichment: None,
+        };
+
+        let mut progress: RunProgress = RunProgress::new(config.progress_mode, false, true);
+        handle_download(&mut download_info, &mut db_writer, &mut summary, &mut RecordSample::new(0), record_options, &mut progress)
+            .await
+            .unwrap();
+
+        assert_eq!(wait_for_download(join_handle, summary.limit.is_some()).await, ExitCodes::Success);
+        assert_eq!((summary.records_stored, summary.limit), (2, Some(2)));
+
+        // Only the full chunk is sent, which fails as there is no server, without an empty one after it
+        let errors: Vec<DatabaseError> = db_writer.finish_errors().await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].failed_documents(), 2);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+
+use colored::Colorize;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+// Read for the filter directives, which replace the level set by --verbose when given
+pub const LOG_FILTER_VARIABLE: &str = "RUST_LOG";
+
+// Errors that can occur when setting up the logging
+#[derive(Debug)]
+pub enum LoggingError {
+    IoError(std::io::Error),
+    InitError(String),
+}
+
+impl From<std::io::Error> for LoggingError {
+    fn from(error: std::io::Error) -> Self {
+        LoggingError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for LoggingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoggingError::IoError(error) => write!(f, "Log file error: {}", error),
+            LoggingError::InitError(error) => write!(f, "Logging error: {}", error),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // One coloured line per message on a terminal, or timestamped lines in a log file
+    #[default]
+    Text,
+    // One JSON object per message, with the level, target and fields
+    Json,
+}
+
+// The filter for the log messages, RUST_LOG if it is set or this crate's messages at the level for --verbose
+pub fn filter_directives(verbosity: u8, rust_log: Option<&str>) -> String {
+    if let Some(directives) = rust_log.filter(|directives| !directives.trim().is_empty()) {
+        return directives.to_string();
+    }
+
+    let level: Level = match verbosity {
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+
+    format!("{}={}", env!("CARGO_CRATE_NAME"), level)
+}
+
+// Send the status messages to the terminal, or to a log file if one is given
+//
+// On the terminal errors and warnings go to stderr and everything else to stdout, as the plain println output did
+pub fn init(verbosity: u8, format: LogFormat, log_file: Option<&Path>) -> Result<(), LoggingError> {
+    let rust_log: Option<String> = std::env::var(LOG_FILTER_VARIABLE).ok();
+    let filter: EnvFilter = EnvFilter::new(filter_directives(verbosity, rust_log.as_deref()));
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let result = match (format, log_file) {
+        (LogFormat::Text, None) => builder
+            .event_format(StatusLines)
+            .with_writer(std::io::stderr.with_max_level(Level::WARN).or_else(std::io::stdout))
+            .try_init(),
+        (LogFormat::Json, None) => builder
+            .json()
+            .with_writer(std::io::stderr.with_max_level(Level::WARN).or_else(std::io::stdout))
+            .try_init(),
+        (LogFormat::Text, Some(path)) => builder.with_ansi(false).with_writer(open_log_file(path)?).try_init(),
+        (LogFormat::Json, Some(path)) => builder.json().with_writer(open_log_file(path)?).try_init(),
+    };
+
+    result.map_err(|error| LoggingError::InitError(error.to_string()))
+}
+
+// Append to the log file, so a service restarted by systemd keeps the earlier runs
+fn open_log_file(path: &Path) -> std::io::Result<Mutex<File>> {
+    let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Mutex::new(file))
+}
+
+// Prints just the message, coloured by level, as the status lines were before they were logged
+struct StatusLines;
+
+impl<S, N> FormatEvent<S, N> for StatusLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, _ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> std::fmt::Result {
+        let mut line: StatusLine = StatusLine::default();
+        event.record(&mut line);
+
+        let ansi: bool = writer.has_ansi_escapes();
+        writeln!(writer, "{}", line.render(*event.metadata().level(), ansi))
+    }
+}
+
+// The fields of one status message, success and notice pick the colour of an info message
+#[derive(Default)]
+struct StatusLine {
+    message: String,
+    success: bool,
+    notice: bool,
+    fields: String,
+}
+
+impl StatusLine {
+    fn render(&self, level: Level, ansi: bool) -> String {
+        let text: String = format!("{}{}", self.message, self.fields);
+
+        if !ansi {
+            return text;
+        }
+
+        let coloured = match level {
+            Level::ERROR => text.red().bold(),
+            Level::WARN => text.yellow().bold(),
+            Level::INFO if self.success => text.green().bold(),
+            Level::INFO if self.notice => text.yellow().bold(),
+            Level::INFO => text.blue().bold(),
+            _ => text.dimmed(),
+        };
+
+        coloured.to_string()
+    }
+}
+
+impl Visit for StatusLine {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        match field.name() {
+            "success" => self.success = value,
+            "notice" => self.notice = value,
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_raises_the_level_unless_rust_log_is_set() {
+        assert_eq!(filter_directives(0, None), "opensky_downloader=INFO");
+        assert_eq!(filter_directives(1, None), "opensky_downloader=DEBUG");
+        assert_eq!(filter_directives(3, Some("")), "opensky_downloader=TRACE");
+        assert_eq!(filter_directives(1, Some("opensky_downloader=warn")), "opensky_downloader=warn");
+    }
+
+    #[test]
+    fn status_lines_keep_the_fields_without_colour() {
+        let line: StatusLine = StatusLine {
+            message: "Inserted a chunk".to_string(),
+            fields: " documents=1000".to_string(),
+            ..StatusLine::default()
+        };
+
+        assert_eq!(line.render(Level::DEBUG, false), "Inserted a chunk documents=1000");
+    }
+}
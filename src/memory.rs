@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{AcquireError, Notify, OwnedSemaphorePermit, Semaphore};
+
+// The budget is shared out in units of this many bytes, so a semaphore permit count fits a u32
+const UNIT_BYTES: usize = 1024;
+
+// Bounds the estimated size of the records read but not yet stored, from the channel through to
+// the chunks being inserted, pausing the reader of the source while the budget is used up
+pub struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    units: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+    // Notified when the reader is waiting, so a partly filled chunk holding the memory can be sent
+    exhausted: Notify,
+}
+
+// The share of the budget held by one record, returned when the record is dropped
+pub struct MemoryPermit {
+    _permit: OwnedSemaphorePermit,
+    bytes: usize,
+    budget: Arc<MemoryBudget>,
+}
+
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(bytes: usize) -> Arc<Self> {
+        let units: usize = (bytes / UNIT_BYTES).max(1);
+
+        Arc::new(MemoryBudget {
+            semaphore: Arc::new(Semaphore::new(units)),
+            units,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            exhausted: Notify::new(),
+        })
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.units * UNIT_BYTES
+    }
+
+    // The most the records held at once were estimated to use
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    // Wait until there is room for a record of this many bytes, a record larger than the whole
+    // budget waits for all of it rather than forever
+    pub async fn acquire(self: &Arc<Self>, bytes: usize) -> Result<MemoryPermit, AcquireError> {
+        let units: u32 = bytes.div_ceil(UNIT_BYTES).clamp(1, self.units) as u32;
+
+        let permit: OwnedSemaphorePermit = match self.semaphore.clone().try_acquire_many_owned(units) {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.exhausted.notify_one();
+                self.semaphore.clone().acquire_many_owned(units).await?
+            }
+        };
+
+        let used: usize = self.used.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.peak.fetch_max(used, Ordering::SeqCst);
+
+        Ok(MemoryPermit {
+            _permit: permit,
+            bytes,
+            budget: self.clone(),
+        })
+    }
+
+    // Return once the reader has had to wait for memory
+    pub async fn exhausted(&self) {
+        self.exhausted.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn the_reader_waits_until_records_are_dropped() {
+        let budget: Arc<MemoryBudget> = MemoryBudget::new(4 * UNIT_BYTES);
+
+        let first: MemoryPermit = budget.acquire(3 * UNIT_BYTES).await.unwrap();
+        let second: MemoryPermit = budget.acquire(100).await.unwrap();
+        assert_eq!(budget.peak(), 3 * UNIT_BYTES + 100);
+
+        // No room for another unit, so the reader waits and says so
+        let waiting: Arc<MemoryBudget> = budget.clone();
+        let reader = tokio::spawn(async move { waiting.acquire(UNIT_BYTES).await.map(|_| ()) });
+
+        assert!(tokio::time::timeout(Duration::from_secs(1), budget.exhausted()).await.is_ok());
+        assert!(!reader.is_finished());
+
+        drop(first);
+        assert!(tokio::time::timeout(Duration::from_secs(1), reader).await.is_ok());
+
+        drop(second);
+        assert_eq!(budget.used.load(Ordering::SeqCst), 0);
+        assert_eq!(budget.peak(), 3 * UNIT_BYTES + 100);
+    }
+
+    #[tokio::test]
+    async fn records_larger_than_the_budget_still_fit() {
+        let budget: Arc<MemoryBudget> = MemoryBudget::new(2 * UNIT_BYTES);
+
+        let permit = tokio::time::timeout(Duration::from_secs(1), budget.acquire(10 * UNIT_BYTES)).await;
+        assert!(permit.is_ok());
+    }
+}
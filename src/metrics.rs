@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The counters are per process like the signals, one run is counted by this one set
+static METRICS: RunMetrics = RunMetrics::new();
+
+// Counters kept as the run progresses, read by the metrics reporters
+pub struct RunMetrics {
+    records_read: AtomicU64,
+    records_stored: AtomicU64,
+    bytes_read: AtomicU64,
+    errors: AtomicU64,
+    warnings: AtomicU64,
+}
+
+// The values of the counters at one moment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub records_read: u64,
+    pub records_stored: u64,
+    pub bytes_read: u64,
+    pub errors: u64,
+    pub warnings: u64,
+}
+
+impl RunMetrics {
+    pub const fn new() -> Self {
+        RunMetrics {
+            records_read: AtomicU64::new(0),
+            records_stored: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            warnings: AtomicU64::new(0),
+        }
+    }
+
+    // Count a record read from the source, the position of a record is the number of bytes before it
+    pub fn record_read(&self, position: u64) {
+        self.records_read.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_max(position, Ordering::Relaxed);
+    }
+
+    pub fn record_stored(&self) {
+        self.records_stored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn warning(&self) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            records_read: self.records_read.load(Ordering::Relaxed),
+            records_stored: self.records_stored.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            warnings: self.warnings.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for RunMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsSnapshot {
+    // How much each counter has grown since an earlier snapshot
+    pub fn since(&self, earlier: &MetricsSnapshot) -> MetricsSnapshot {
+        MetricsSnapshot {
+            records_read: self.records_read.saturating_sub(earlier.records_read),
+            records_stored: self.records_stored.saturating_sub(earlier.records_stored),
+            bytes_read: self.bytes_read.saturating_sub(earlier.bytes_read),
+            errors: self.errors.saturating_sub(earlier.errors),
+            warnings: self.warnings.saturating_sub(earlier.warnings),
+        }
+    }
+}
+
+// The counters for this run
+pub fn metrics() -> &'static RunMetrics {
+    &METRICS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_give_the_growth_between_them() {
+        let metrics: RunMetrics = RunMetrics::new();
+
+        metrics.record_read(0);
+        metrics.record_read(120);
+        metrics.record_stored();
+        let first: MetricsSnapshot = metrics.snapshot();
+        assert_eq!((first.records_read, first.records_stored, first.bytes_read), (2, 1, 120));
+
+        // Positions can arrive out of order from parallel ranges, the bytes only grow
+        metrics.record_read(300);
+        metrics.record_read(250);
+        metrics.error();
+
+        let growth: MetricsSnapshot = metrics.snapshot().since(&first);
+        assert_eq!(
+            growth,
+            MetricsSnapshot {
+                records_read: 2,
+                records_stored: 0,
+                bytes_read: 180,
+                errors: 1,
+                warnings: 0,
+            }
+        );
+    }
+}
@@ -1,18 +1,45 @@
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Serialize};
+
+use crate::enrichment::Enrichment;
+
+// The number of hex digits in a full ICAO24 address
+const ICAO24_LENGTH: usize = 6;
+
+// What --pad-icao did with the ICAO24 address of a record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Icao24Padding {
+    Unchanged,
+    Padded,
+    // Not hex, or longer than six digits, so it can't be a valid address
+    Invalid,
+}
+
+// The name of the derived age field, which isn't read from the CSV so isn't one of the field names
+pub const AGE_YEARS_FIELD: &str = "ageYears";
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Default))]
 pub struct Aircraft {
     pub icao24: String,
-    timestamp: String,
+    pub timestamp: String,
     acars: String,
     adsb: String,
-    built: String,
+    // The year at the start of the built date, so it can be compared as a number
+    #[serde(deserialize_with = "built_year_cell")]
+    pub built: Option<i32>,
     #[serde(rename = "categoryDescription")]
-    category_description: String,
+    pub category_description: String,
     country: String,
-    engines: String,
-    #[serde(rename = "firstFlightDate")]
-    firstflightdate: String,
+    #[serde(deserialize_with = "number_cell")]
+    engines: Option<u32>,
+    // The dates are stored as BSON dates, so they can be queried by range
+    #[serde(rename = "firstFlightDate", deserialize_with = "date_cell")]
+    firstflightdate: Option<bson::DateTime>,
     #[serde(rename = "firstSeen")]
     first_seen: String,
     #[serde(rename = "icaoAircraftClass")]
@@ -20,9 +47,9 @@ pub struct Aircraft {
     #[serde(rename = "lineNumber")]
     line_number: String,
     #[serde(rename = "manufacturerIcao")]
-    manufacturer_icao: String,
+    pub manufacturer_icao: String,
     #[serde(rename = "manufacturerName")]
-    manufacturer_name: String,
+    pub manufacturer_name: String,
     model: String,
     modes: String,
     #[serde(rename = "nextReg")]
@@ -37,9 +64,10 @@ pub struct Aircraft {
     owner: String,
     #[serde(rename = "prevReg")]
     prev_reg: String,
-    #[serde(rename = "regUntil")]
-    reg_until: String,
-    registered: String,
+    #[serde(rename = "regUntil", deserialize_with = "date_cell")]
+    reg_until: Option<bson::DateTime>,
+    #[serde(deserialize_with = "date_cell")]
+    registered: Option<bson::DateTime>,
     registration: String,
     #[serde(rename = "selCal")]
     sel_cal: String,
@@ -48,4 +76,604 @@ pub struct Aircraft {
     status: String,
     typecode: String,
     vdl: String,
+    // Derived from built, not read from the CSV
+    #[serde(rename = "ageYears", skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub age_years: Option<i32>,
+}
+
+// A row of airport metadata, loaded with --dataset airports, the fields are the columns of the
+// OurAirports airports.csv
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Default))]
+pub struct Airport {
+    #[serde(deserialize_with = "number_cell")]
+    id: Option<u64>,
+    pub ident: String,
+    #[serde(rename = "type")]
+    airport_type: String,
+    name: String,
+    #[serde(deserialize_with = "number_cell")]
+    latitude_deg: Option<f64>,
+    #[serde(deserialize_with = "number_cell")]
+    longitude_deg: Option<f64>,
+    #[serde(deserialize_with = "number_cell")]
+    elevation_ft: Option<i32>,
+    continent: String,
+    iso_country: String,
+    iso_region: String,
+    municipality: String,
+    scheduled_service: String,
+    gps_code: String,
+    pub iata_code: String,
+    local_code: String,
+    home_link: String,
+    wikipedia_link: String,
+    keywords: String,
+}
+
+// Decides which records read from the source are stored, and normalises those that are
+pub trait FilterMap: Sized {
+    // Whether the record should be stored
+    fn filter(&self) -> bool;
+
+    // The record as it should be stored
+    fn map(self) -> Self;
+}
+
+impl FilterMap for Aircraft {
+    // A record without an ICAO24 address can't be looked up, so it isn't stored
+    fn filter(&self) -> bool {
+        !self.icao24.is_empty()
+    }
+
+    // Store the ICAO24 address and registration in uppercase, the source mixes both cases, and
+    // trim the registration and collapse its inner spaces so it can be matched exactly
+    fn map(mut self) -> Self {
+        self.icao24 = self.icao24.to_uppercase();
+        self.registration = self.registration.split_whitespace().collect::<Vec<&str>>().join(" ").to_uppercase();
+        self
+    }
+}
+
+impl FilterMap for Airport {
+    // The ident is the key other datasets use for an airport, so a row without one isn't stored
+    fn filter(&self) -> bool {
+        !self.ident.trim().is_empty()
+    }
+
+    // Store the codes trimmed and in uppercase, so they can be matched exactly
+    fn map(mut self) -> Self {
+        self.ident = self.ident.trim().to_uppercase();
+        self.iata_code = self.iata_code.trim().to_uppercase();
+        self
+    }
+}
+
+// A model the load reads and stores, with the steps of the options that only apply to some models
+//
+// A model without the fields an option needs keeps the default, which leaves the record as it is,
+// and the option is rejected for it by Config::validate, e.g. --country with --dataset airports
+pub trait Model: FilterMap + DeserializeOwned + Serialize + Send + Sync + 'static {
+    // Cut string fields longer than max_length characters, returning the names of the fields that were cut
+    fn truncate_fields(&mut self, _max_length: usize) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    // Whether the country is one of those given, which are compared in the form of normalise_country
+    fn country_in(&self, _countries: &[String]) -> bool {
+        true
+    }
+
+    // Left-pad a short ICAO24 address to the full six digits
+    fn pad_icao24(&mut self) -> Icao24Padding {
+        Icao24Padding::Unchanged
+    }
+
+    // Override fields from the lookup file, returning whether any were
+    fn enrich(&mut self, _enrichment: &Enrichment) -> bool {
+        false
+    }
+
+    // Set the age from the year the record was built
+    fn set_age_years(&mut self, _current_year: i32) {}
+
+    // The values whose range is shown in the summary
+    fn timestamp(&self) -> Option<&str> {
+        None
+    }
+
+    fn built_year(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl Model for Aircraft {
+    fn truncate_fields(&mut self, max_length: usize) -> Vec<&'static str> {
+        self.string_fields_mut()
+            .into_iter()
+            .filter_map(|(name, value)| truncate(value, max_length).then_some(name))
+            .collect()
+    }
+
+    fn country_in(&self, countries: &[String]) -> bool {
+        countries.contains(&normalise_country(&self.country))
+    }
+
+    // Some sources strip the leading zeros of the hex address, e.g. 0A1B2C written as A1B2C
+    fn pad_icao24(&mut self) -> Icao24Padding {
+        if self.icao24.len() > ICAO24_LENGTH || !self.icao24.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Icao24Padding::Invalid;
+        }
+
+        if self.icao24.len() == ICAO24_LENGTH {
+            return Icao24Padding::Unchanged;
+        }
+
+        self.icao24 = format!("{:0>width$}", self.icao24, width = ICAO24_LENGTH);
+        Icao24Padding::Padded
+    }
+
+    fn enrich(&mut self, enrichment: &Enrichment) -> bool {
+        enrichment.apply(self)
+    }
+
+    // The age is left None if built doesn't start with a valid year
+    fn set_age_years(&mut self, current_year: i32) {
+        self.age_years = self
+            .built
+            .map(|built_year| current_year - built_year)
+            .filter(|age_years| *age_years >= 0);
+    }
+
+    fn timestamp(&self) -> Option<&str> {
+        Some(&self.timestamp)
+    }
+
+    fn built_year(&self) -> Option<i32> {
+        self.built
+    }
+}
+
+impl Model for Airport {}
+
+impl Aircraft {
+    // Every string field with the name it is stored under
+    fn string_fields_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        vec![
+            ("icao24", &mut self.icao24),
+            ("timestamp", &mut self.timestamp),
+            ("acars", &mut self.acars),
+            ("adsb", &mut self.adsb),
+            ("categoryDescription", &mut self.category_description),
+            ("country", &mut self.country),
+            ("firstSeen", &mut self.first_seen),
+            ("icaoAircraftClass", &mut self.icao_aircraft_class),
+            ("lineNumber", &mut self.line_number),
+            ("manufacturerIcao", &mut self.manufacturer_icao),
+            ("manufacturerName", &mut self.manufacturer_name),
+            ("model", &mut self.model),
+            ("modes", &mut self.modes),
+            ("nextReg", &mut self.next_reg),
+            ("operator", &mut self.operator),
+            ("operatorCallsign", &mut self.operator_callsign),
+            ("operatorIata", &mut self.operator_iata),
+            ("operatorIcao", &mut self.operator_icao),
+            ("owner", &mut self.owner),
+            ("prevReg", &mut self.prev_reg),
+            ("registration", &mut self.registration),
+            ("selCal", &mut self.sel_cal),
+            ("serialNumber", &mut self.serial_number),
+            ("status", &mut self.status),
+            ("typecode", &mut self.typecode),
+            ("vdl", &mut self.vdl),
+        ]
+    }
+}
+
+// Countries are matched trimmed and case-insensitively, e.g. " united kingdom" matches "United Kingdom"
+pub fn normalise_country(country: &str) -> String {
+    country.trim().to_lowercase()
+}
+
+// Read the year from a built value, either a full date such as "2005-03-01" or just "2005"
+fn built_year(built: &str) -> Option<i32> {
+    let built: &str = built.trim();
+    let (year, rest) = built.split_at_checked(4)?;
+
+    match year.bytes().all(|byte| byte.is_ascii_digit()) && (rest.is_empty() || rest.starts_with('-')) {
+        true => year.parse().ok(),
+        false => None,
+    }
+}
+
+// Read a number from a CSV cell, OpenSky leaves many cells blank and some hold text, both of
+// which are None rather than failing the whole row
+fn number_cell<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+{
+    let value: String = String::deserialize(deserializer)?;
+    Ok(value.trim().parse().ok())
+}
+
+// Read the year from a built cell, None if it is blank or has no valid year
+fn built_year_cell<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = String::deserialize(deserializer)?;
+    Ok(built_year(&value))
+}
+
+// Read a date from a CSV cell, None if it is blank or not a valid date
+fn date_cell<'de, D>(deserializer: D) -> Result<Option<bson::DateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = String::deserialize(deserializer)?;
+    Ok(parse_date(&value))
+}
+
+// Parse a date such as "2005-03-01", at midnight UTC, or one with a time such as "2005-03-01 10:00:00"
+fn parse_date(value: &str) -> Option<bson::DateTime> {
+    let value: &str = value.trim();
+
+    let date_time: NaiveDateTime = match NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        Ok(date_time) => date_time,
+        Err(_) => NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0)?,
+    };
+
+    Some(bson::DateTime::from_millis(date_time.and_utc().timestamp_millis()))
+}
+
+// Cut a value to at most max_length characters, returning whether it was cut
+fn truncate(value: &mut String, max_length: usize) -> bool {
+    match value.char_indices().nth(max_length) {
+        Some((index, _)) => {
+            value.truncate(index);
+            true
+        }
+        None => false,
+    }
+}
+
+// The differences between the columns of a file and the fields of a model
+pub struct SchemaDrift {
+    // Columns in the file that the model doesn't read
+    pub extra: Vec<String>,
+
+    // Fields of the model with no column in the file
+    pub missing: Vec<&'static str>,
+}
+
+impl SchemaDrift {
+    pub fn new<'a, D>(columns: impl Iterator<Item = &'a str> + Clone) -> Self
+    where
+        D: DeserializeOwned,
+    {
+        let fields: &'static [&'static str] = field_names::<D>();
+
+        SchemaDrift {
+            extra: columns
+                .clone()
+                .filter(|column| !fields.contains(column))
+                .map(str::to_string)
+                .collect(),
+            missing: fields
+                .iter()
+                .filter(|field| !columns.clone().any(|column| column == **field))
+                .copied()
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extra.is_empty() && self.missing.is_empty()
+    }
+}
+
+// Get the field names a model expects to deserialize, after any serde renames
+pub fn field_names<D>() -> &'static [&'static str]
+where
+    D: DeserializeOwned,
+{
+    let mut fields: &'static [&'static str] = &[];
+
+    // The derived Deserialize impl passes its field names to deserialize_struct, capture them and bail out
+    let _ = D::deserialize(FieldNamesDeserializer {
+        fields: &mut fields,
+    });
+
+    fields
+}
+
+struct FieldNamesDeserializer<'a> {
+    fields: &'a mut &'static [&'static str],
+}
+
+impl<'de> Deserializer<'de> for FieldNamesDeserializer<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom("only structs have field names"))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        *self.fields = fields;
+        Err(de::Error::custom("field names captured"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    #[test]
+    fn reads_the_year_from_full_dates_and_years() {
+        assert_eq!(built_year("2005-03-01"), Some(2005));
+        assert_eq!(built_year("2005"), Some(2005));
+        assert_eq!(built_year(" 1999 "), Some(1999));
+    }
+
+    #[test]
+    fn ignores_empty_and_garbage_values() {
+        for built in ["", "   ", "05", "unknown", "20O5", "20051", "2005/03/01", "-2005"] {
+            assert_eq!(built_year(built), None, "{:?}", built);
+        }
+    }
+
+    #[test]
+    fn short_icao24_addresses_are_padded() {
+        let mut aircraft: Aircraft = Aircraft { icao24: "A1B2C".to_string(), ..Default::default() };
+        assert_eq!(aircraft.pad_icao24(), Icao24Padding::Padded);
+        assert_eq!(aircraft.icao24, "0A1B2C");
+
+        let mut aircraft: Aircraft = Aircraft { icao24: "4CA1FA".to_string(), ..Default::default() };
+        assert_eq!(aircraft.pad_icao24(), Icao24Padding::Unchanged);
+        assert_eq!(aircraft.icao24, "4CA1FA");
+    }
+
+    #[test]
+    fn invalid_icao24_addresses_are_not_padded() {
+        for icao24 in ["4CA1FAB", "G1B2C", "A1 B2", "-1B2C"] {
+            let mut aircraft: Aircraft = Aircraft { icao24: icao24.to_string(), ..Default::default() };
+            assert_eq!(aircraft.pad_icao24(), Icao24Padding::Invalid);
+            assert_eq!(aircraft.icao24, icao24);
+        }
+    }
+
+    #[test]
+    fn sets_the_age_in_years() {
+        let mut aircraft: Aircraft = Aircraft {
+            built: Some(2005),
+            ..Aircraft::default()
+        };
+
+        aircraft.set_age_years(2026);
+        assert_eq!(aircraft.age_years, Some(21));
+
+        // A build year in the future is treated as invalid
+        aircraft.built = Some(2030);
+        aircraft.set_age_years(2026);
+        assert_eq!(aircraft.age_years, None);
+
+        aircraft.built = None;
+        aircraft.set_age_years(2026);
+        assert_eq!(aircraft.age_years, None);
+    }
+
+    #[test]
+    fn records_without_an_icao24_are_filtered_out() {
+        let aircraft: Aircraft = Aircraft::default();
+        assert!(!aircraft.filter());
+
+        let aircraft: Aircraft = Aircraft {
+            icao24: "4ca1fa".to_string(),
+            ..Aircraft::default()
+        };
+        assert!(aircraft.filter());
+    }
+
+    #[test]
+    fn countries_are_matched_trimmed_and_ignoring_case() {
+        let aircraft: Aircraft = Aircraft {
+            country: " United Kingdom ".to_string(),
+            ..Aircraft::default()
+        };
+
+        let countries: Vec<String> = ["united kingdom", "GERMANY "].map(normalise_country).to_vec();
+        assert!(aircraft.country_in(&countries));
+        assert!(!aircraft.country_in(&[normalise_country("Germany")]));
+        assert!(!Aircraft::default().country_in(&countries));
+    }
+
+    #[test]
+    fn airports_without_an_ident_are_filtered_out_and_codes_uppercased() {
+        assert!(!Airport::default().filter());
+
+        let airport: Airport = Airport {
+            ident: " egll".to_string(),
+            iata_code: "lhr ".to_string(),
+            ..Airport::default()
+        };
+        assert!(airport.filter());
+
+        let airport: Airport = airport.map();
+        assert_eq!((airport.ident.as_str(), airport.iata_code.as_str()), ("EGLL", "LHR"));
+    }
+
+    #[test]
+    fn icao24_addresses_are_uppercased() {
+        let aircraft: Aircraft = Aircraft {
+            icao24: "4ca1fa".to_string(),
+            ..Aircraft::default()
+        };
+
+        assert_eq!(aircraft.map().icao24, "4CA1FA");
+    }
+
+    #[test]
+    fn registrations_are_trimmed_uppercased_and_collapsed() {
+        let aircraft: Aircraft = Aircraft {
+            icao24: "4ca1fa".to_string(),
+            registration: " \tg-abcd  x \n".to_string(),
+            ..Aircraft::default()
+        };
+
+        assert_eq!(aircraft.map().registration, "G-ABCD X");
+    }
+
+    #[test]
+    fn truncates_long_fields_by_characters() {
+        let mut aircraft: Aircraft = Aircraft {
+            icao24: "4ca1fa".to_string(),
+            owner: "Aer Lingus".to_string(),
+            model: "Zürich".to_string(),
+            ..Aircraft::default()
+        };
+
+        assert_eq!(aircraft.truncate_fields(10), Vec::<&str>::new());
+
+        assert_eq!(aircraft.truncate_fields(3), ["icao24", "model", "owner"]);
+        assert_eq!(aircraft.icao24, "4ca");
+        assert_eq!(aircraft.owner, "Aer");
+        assert_eq!(aircraft.model, "Zür");
+    }
+
+    #[test]
+    fn string_fields_match_the_model() {
+        let names: Vec<&str> = Aircraft::default().string_fields_mut().into_iter().map(|(name, _)| name).collect();
+
+        // Every field read from the CSV is a string, apart from the typed ones
+        let string_fields: Vec<&str> = field_names::<Aircraft>()
+            .iter()
+            .copied()
+            .filter(|name| !["built", "engines", "firstFlightDate", "regUntil", "registered"].contains(name))
+            .collect();
+
+        assert_eq!(names, string_fields);
+    }
+
+    #[test]
+    fn dates_are_parsed_and_others_are_none() {
+        let midnight: bson::DateTime = bson::DateTime::parse_rfc3339_str("2005-03-01T00:00:00Z").unwrap();
+        assert_eq!(parse_date("2005-03-01"), Some(midnight));
+        assert_eq!(parse_date(" 2005-03-01 "), Some(midnight));
+
+        let ten_am: bson::DateTime = bson::DateTime::parse_rfc3339_str("2005-03-01T10:00:00Z").unwrap();
+        assert_eq!(parse_date("2005-03-01 10:00:00"), Some(ten_am));
+
+        for value in ["", "   ", "2005", "2005-02-30", "01/03/2005", "2005-03-01x", "unknown"] {
+            assert_eq!(parse_date(value), None, "{:?}", value);
+        }
+    }
+
+    // A CSV row in field order with the given cells filled in and the rest blank
+    fn csv_row(cells: &[(&str, &str)]) -> String {
+        field_names::<Aircraft>()
+            .iter()
+            .map(|name| cells.iter().find(|(field, _)| field == name).map_or("", |(_, value)| *value))
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
+
+    #[tokio::test]
+    async fn blank_numeric_cells_are_none() {
+        let csv: String = [
+            field_names::<Aircraft>().join(","),
+            csv_row(&[("icao24", "4ca1fa")]),
+            csv_row(&[("icao24", "4ca1fb"), ("built", "2005-03-01"), ("engines", "2")]),
+            csv_row(&[("icao24", "4ca1fc"), ("built", "unknown"), ("engines", "CFM56-5B4/P")]),
+        ]
+        .join("\n");
+
+        let mut reader = csv_async::AsyncDeserializer::from_reader(csv.as_bytes());
+        let records: Vec<Aircraft> = reader.deserialize::<Aircraft>().try_collect().await.unwrap();
+
+        assert_eq!((records[0].engines, records[0].built), (None, None));
+        assert_eq!((records[1].engines, records[1].built), (Some(2), Some(2005)));
+
+        // Text where a number should be doesn't fail the row either
+        assert_eq!((records[2].engines, records[2].built), (None, None));
+        assert_eq!(records[2].icao24, "4ca1fc");
+
+        // Stored as numbers, so they can be compared in queries
+        let document: bson::Document = bson::to_document(&records[1]).unwrap();
+        assert_eq!(document.get_i32("built"), Ok(2005));
+        assert_eq!(document.get_i64("engines"), Ok(2));
+    }
+
+    #[tokio::test]
+    async fn reads_an_airport_row() {
+        let csv: String = [
+            field_names::<Airport>().join(","),
+            "2434,EGLL,large_airport,London Heathrow Airport,51.4706,-0.461941,83,EU,GB,GB-ENG,London,yes,EGLL,LHR,,,,".to_string(),
+        ]
+        .join("\n");
+
+        let mut reader = csv_async::AsyncDeserializer::from_reader(csv.as_bytes());
+        let records: Vec<Airport> = reader.deserialize::<Airport>().try_collect().await.unwrap();
+
+        let document: bson::Document = bson::to_document(&records[0]).unwrap();
+        assert_eq!(document.get_str("type"), Ok("large_airport"));
+        assert_eq!(document.get_f64("latitude_deg"), Ok(51.4706));
+        assert_eq!(document.get_i32("elevation_ft"), Ok(83));
+        assert_eq!(document.get_str("iata_code"), Ok("LHR"));
+    }
+
+    #[tokio::test]
+    async fn date_cells_are_stored_as_bson_dates() {
+        let csv: String = [
+            field_names::<Aircraft>().join(","),
+            csv_row(&[("icao24", "4ca1fa"), ("registered", "2005-03-01"), ("regUntil", "31/12/2030")]),
+        ]
+        .join("\n");
+
+        let mut reader = csv_async::AsyncDeserializer::from_reader(csv.as_bytes());
+        let records: Vec<Aircraft> = reader.deserialize::<Aircraft>().try_collect().await.unwrap();
+
+        // A valid date, a malformed one and an empty one
+        let document: bson::Document = bson::to_document(&records[0]).unwrap();
+        assert_eq!(document.get_datetime("registered"), Ok(&parse_date("2005-03-01").unwrap()));
+        assert_eq!(document.get("regUntil"), Some(&bson::Bson::Null));
+        assert_eq!(document.get("firstFlightDate"), Some(&bson::Bson::Null));
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Row {
+        icao24: String,
+        #[serde(rename = "manufacturerName")]
+        manufacturer_name: String,
+    }
+
+    #[test]
+    fn finds_added_and_removed_columns() {
+        let drift: SchemaDrift = SchemaDrift::new::<Row>(["icao24", "manufacturerName"].into_iter());
+        assert!(drift.is_empty());
+
+        let drift: SchemaDrift = SchemaDrift::new::<Row>(["icao24", "manufacturer_name", "vdl"].into_iter());
+        assert_eq!(drift.extra, ["manufacturer_name", "vdl"]);
+        assert_eq!(drift.missing, ["manufacturerName"]);
+    }
 }
@@ -0,0 +1,101 @@
+use tokio::sync::watch;
+
+use tracing::info;
+
+// Lets the reader of the source wait while a download is paused
+//
+// Reading stops between records, so nothing more is pulled from the connection and TCP flow control
+// holds the server back, only the few kilobytes already buffered are read ahead
+#[derive(Clone)]
+pub struct Pause {
+    receiver: watch::Receiver<bool>,
+}
+
+// Pauses and resumes the downloads holding the matching Pause
+pub struct PauseSwitch {
+    sender: watch::Sender<bool>,
+}
+
+// A switch and the pause it controls, starting unpaused
+pub fn pause_switch() -> (PauseSwitch, Pause) {
+    let (sender, receiver) = watch::channel(false);
+    (PauseSwitch { sender }, Pause { receiver })
+}
+
+impl PauseSwitch {
+    // Flip between paused and running, returning whether it is now paused
+    pub fn toggle(&self) -> bool {
+        self.sender.send_modify(|paused| *paused = !*paused);
+        *self.sender.borrow()
+    }
+}
+
+impl Pause {
+    pub fn is_paused(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Return once the download isn't paused, straight away if it is running
+    pub async fn wait_while_paused(&mut self) {
+        // If the switch has gone the download can never be resumed, so carry on
+        let _ = self.receiver.wait_for(|paused| !paused).await;
+    }
+}
+
+// A pause toggled each time the process receives SIGUSR1, e.g. from kill -USR1 <pid>
+#[cfg(unix)]
+pub fn toggle_on_sigusr1() -> std::io::Result<Pause> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = signal(SignalKind::user_defined1())?;
+    let (switch, pause) = pause_switch();
+
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            let text: String = match switch.toggle() {
+                true => format!("Download paused, send SIGUSR1 to process {} again to resume", std::process::id()),
+                false => "Download resumed".to_string(),
+            };
+            info!(notice = true, "{}", text);
+        }
+    });
+
+    Ok(pause)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn waiting_returns_once_resumed() {
+        let (switch, mut pause) = pause_switch();
+
+        // Running, so there is nothing to wait for
+        pause.wait_while_paused().await;
+
+        assert!(switch.toggle());
+        assert!(pause.is_paused());
+
+        // Still paused, so the wait doesn't finish
+        assert!(tokio::time::timeout(Duration::from_millis(20), pause.wait_while_paused()).await.is_err());
+
+        let mut waiting: Pause = pause.clone();
+        let waiter = tokio::spawn(async move { waiting.wait_while_paused().await });
+
+        assert!(!switch.toggle());
+        assert!(tokio::time::timeout(Duration::from_secs(1), waiter).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_dropped_switch_never_blocks() {
+        let (switch, mut pause) = pause_switch();
+
+        switch.toggle();
+        drop(switch);
+
+        assert!(tokio::time::timeout(Duration::from_secs(1), pause.wait_while_paused()).await.is_ok());
+    }
+}
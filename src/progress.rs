@@ -0,0 +1,496 @@
+use std::io::IsTerminal;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use indicatif::{style, HumanBytes, HumanDuration, ProgressBar};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Log a plain progress line every PLAIN_PERCENT_STEP percent, or every PLAIN_INTERVAL if the length is unknown
+const PLAIN_PERCENT_STEP: u64 = 10;
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+// Update the indicator at most 20 times a second, updating per record slows large runs
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+// The overall indicator counts in tenths of a percent so the ETA moves smoothly
+const OVERALL_LENGTH: u64 = 1000;
+
+// Rough costs used to weigh the download against the inserts before any timings are known, the
+// ETA of the overall indicator corrects itself from the measured rate as the run goes
+const ESTIMATED_RECORD_BYTES: f64 = 250.0;
+const ESTIMATED_DOWNLOAD_BYTES_PER_SECOND: f64 = 4.0 * 1024.0 * 1024.0;
+const ESTIMATED_INSERT_RECORDS_PER_SECOND: f64 = 15_000.0;
+// The inserts run alongside the download, but at least this share of them is left when it ends
+const MIN_INSERT_BACKLOG: f64 = 0.1;
+// The share of the run given to the download when the content length is unknown
+const DEFAULT_DOWNLOAD_SHARE: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// A live updating progress bar
+    Bar,
+
+    /// Periodic percentage lines, suitable for log files
+    Plain,
+}
+
+impl ProgressMode {
+    // Use the requested mode, or pick one based on whether stdout is a terminal
+    pub fn resolve(mode: Option<ProgressMode>) -> ProgressMode {
+        match mode {
+            Some(mode) => mode,
+            None => match std::io::stdout().is_terminal() {
+                true => ProgressMode::Bar,
+                false => ProgressMode::Plain,
+            },
+        }
+    }
+}
+
+// What the position of a progress indicator counts
+#[derive(Clone, Copy)]
+pub enum ProgressUnits {
+    Bytes,
+    Percent,
+}
+
+pub struct Progress {
+    indicator: Indicator,
+    last_update: Option<Instant>,
+    pending: Option<u64>,
+}
+
+enum Indicator {
+    Bar(ProgressBar),
+    Plain(PlainProgress),
+    Disabled,
+}
+
+pub struct PlainProgress {
+    message: String,
+    length: u64,
+    units: ProgressUnits,
+    next_percent: u64,
+    last_report: Instant,
+    // Add the estimated time left to each line, from the rate since the start
+    eta: bool,
+    started: Instant,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode, length: u64, message: &str, units: ProgressUnits) -> Self {
+        let indicator: Indicator = match mode {
+            ProgressMode::Bar => {
+                // Use a spinner if the length is unknown, e.g. when reading from stdin
+                let template: &str = match (units, length) {
+                    (ProgressUnits::Bytes, 0) => "{spinner:.green} {msg} [{elapsed_precise}] {bytes}",
+                    (ProgressUnits::Bytes, _) => "{spinner:.green} {msg} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+                    (ProgressUnits::Percent, _) => "{spinner:.green} {msg} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% ({eta})",
+                };
+
+                // Set up the progress bar
+                match style::ProgressStyle::default_bar().template(template) {
+                    Ok(progress_bar_style) => Indicator::Bar(
+                        ProgressBar::new(length)
+                            .with_style(progress_bar_style)
+                            .with_message(message.to_string()),
+                    ),
+                    Err(_) => {
+                        tracing::error!("Failed to create progress bar");
+                        Indicator::Disabled
+                    }
+                }
+            }
+            ProgressMode::Plain => Indicator::Plain(PlainProgress {
+                message: message.trim().to_string(),
+                length,
+                units,
+                next_percent: PLAIN_PERCENT_STEP,
+                last_report: Instant::now(),
+                eta: false,
+                started: Instant::now(),
+            }),
+        };
+
+        Progress {
+            indicator,
+            last_update: None,
+            pending: None,
+        }
+    }
+
+    // Show the estimated time left on plain lines too, the bar always shows it
+    pub fn with_eta(mut self) -> Self {
+        if let Indicator::Plain(plain) = &mut self.indicator {
+            plain.eta = true;
+        }
+
+        self
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        // Hold the position back if the indicator was updated recently, the latest
+        // position is shown on the next update or when finishing
+        if let Some(last_update) = self.last_update {
+            if last_update.elapsed() < UPDATE_INTERVAL {
+                self.pending = Some(position);
+                return;
+            }
+        }
+
+        self.last_update = Some(Instant::now());
+        self.pending = None;
+        self.indicator.set_position(position);
+    }
+
+    pub fn finish(&mut self) {
+        // Show the last position that was held back
+        if let Some(position) = self.pending.take() {
+            self.indicator.set_position(position);
+        }
+
+        match &self.indicator {
+            Indicator::Bar(progress_bar) => progress_bar.finish(),
+            Indicator::Plain(plain) => plain.finish(),
+            Indicator::Disabled => {}
+        }
+    }
+}
+
+impl Indicator {
+    fn set_position(&mut self, position: u64) {
+        match self {
+            Indicator::Bar(progress_bar) => progress_bar.set_position(position),
+            Indicator::Plain(plain) => plain.set_position(position),
+            Indicator::Disabled => {}
+        }
+    }
+}
+
+impl PlainProgress {
+    fn set_position(&mut self, position: u64) {
+        match self.length {
+            // Unknown length, report on a timer
+            0 => {
+                if self.last_report.elapsed() >= PLAIN_INTERVAL {
+                    self.last_report = Instant::now();
+                    println!("{}: {}", self.message, self.format_position(position));
+                }
+            }
+            // Known length, report each time another step is passed
+            length => {
+                let percent: u64 = position.saturating_mul(100) / length;
+
+                if percent >= self.next_percent && self.next_percent < 100 {
+                    match self.eta_text(position, length) {
+                        Some(eta) => println!("{}: {}% (ETA {})", self.message, percent.min(100), eta),
+                        None => println!("{}: {}%", self.message, percent.min(100)),
+                    }
+                    self.next_percent = (percent / PLAIN_PERCENT_STEP + 1) * PLAIN_PERCENT_STEP;
+                }
+            }
+        }
+    }
+
+    fn finish(&self) {
+        println!("{}: done", self.message);
+    }
+
+    // The time left if the rate so far continues
+    fn eta_text(&self, position: u64, length: u64) -> Option<String> {
+        if !self.eta || position == 0 {
+            return None;
+        }
+
+        let remaining: f64 = length.saturating_sub(position) as f64 / position as f64;
+        Some(HumanDuration(self.started.elapsed().mul_f64(remaining)).to_string())
+    }
+
+    fn format_position(&self, position: u64) -> String {
+        match self.units {
+            ProgressUnits::Bytes => HumanBytes(position).to_string(),
+            ProgressUnits::Percent => format!("{}%", position),
+        }
+    }
+}
+
+// The share of the run the download is expected to take, the rest being the inserts still running when it ends
+//
+// The record count is estimated from the content length, less the records a resumed run skips
+pub fn download_share(content_length: u64, skip_records: u64) -> f64 {
+    if content_length == 0 {
+        return DEFAULT_DOWNLOAD_SHARE;
+    }
+
+    let download_seconds: f64 = content_length as f64 / ESTIMATED_DOWNLOAD_BYTES_PER_SECOND;
+    let records: f64 = (content_length as f64 / ESTIMATED_RECORD_BYTES - skip_records as f64).max(0.0);
+    let insert_seconds: f64 = records / ESTIMATED_INSERT_RECORDS_PER_SECOND;
+
+    // Inserts slower than the download leave a backlog, faster ones still leave the last chunks
+    let backlog_seconds: f64 = (insert_seconds - download_seconds).max(insert_seconds * MIN_INSERT_BACKLOG);
+
+    download_seconds / (download_seconds + backlog_seconds)
+}
+
+// The progress of a run through the download and then the inserts still running after it, shown
+// as one overall percentage and ETA, or as a bar for each phase with --verbose-progress
+pub struct RunProgress {
+    mode: ProgressMode,
+    verbose: bool,
+    // Whether inserts follow the download, a file output or dry run ends with the download
+    inserts: bool,
+    download_share: f64,
+    content_length: u64,
+    phase: Option<Progress>,
+    overall: Option<Progress>,
+    position: u64,
+}
+
+impl RunProgress {
+    pub fn new(mode: ProgressMode, verbose: bool, inserts: bool) -> Self {
+        RunProgress {
+            mode,
+            verbose,
+            inserts,
+            download_share: 1.0,
+            content_length: 0,
+            phase: None,
+            overall: None,
+            position: 0,
+        }
+    }
+
+    pub fn start_download(&mut self, content_length: u64, skip_records: u64) {
+        self.content_length = content_length;
+
+        match self.verbose {
+            true => self.phase = Some(Progress::new(self.mode, content_length, "Downloading records", ProgressUnits::Bytes)),
+            false => {
+                self.download_share = match self.inserts {
+                    true => download_share(content_length, skip_records),
+                    false => 1.0,
+                };
+                self.overall = Some(Progress::new(self.mode, OVERALL_LENGTH, "Overall progress   ", ProgressUnits::Percent).with_eta());
+            }
+        }
+    }
+
+    pub fn set_download_position(&mut self, position: u64) {
+        if let Some(phase) = self.phase.as_mut() {
+            phase.set_position(position);
+        }
+
+        // Without a content length the download can't be placed, so the overall waits for the inserts
+        if self.content_length > 0 {
+            let fraction: f64 = (position as f64 / self.content_length as f64).min(1.0);
+            self.set_overall(self.download_share * fraction);
+        }
+    }
+
+    pub fn finish_download(&mut self) {
+        if let Some(mut phase) = self.phase.take() {
+            phase.finish();
+        }
+
+        self.set_overall(self.download_share);
+
+        // Nothing follows the download for a file output or dry run
+        if !self.inserts {
+            self.finish();
+        }
+    }
+
+    pub fn start_inserts(&mut self) {
+        if self.verbose {
+            self.phase = Some(Progress::new(self.mode, 100, "Inserting records  ", ProgressUnits::Percent));
+        }
+    }
+
+    pub fn set_insert_percent(&mut self, percent: f64) {
+        if let Some(phase) = self.phase.as_mut() {
+            phase.set_position(percent as u64);
+        }
+
+        let fraction: f64 = (percent / 100.0).clamp(0.0, 1.0);
+        self.set_overall(self.download_share + (1.0 - self.download_share) * fraction);
+    }
+
+    // Finish whichever indicators are still showing, e.g. when the run stops early
+    pub fn finish(&mut self) {
+        if let Some(mut phase) = self.phase.take() {
+            phase.finish();
+        }
+
+        if let Some(mut overall) = self.overall.take() {
+            overall.finish();
+        }
+    }
+
+    // Move the overall indicator forward, it never goes back when the phase changes
+    fn set_overall(&mut self, fraction: f64) {
+        let position: u64 = (fraction * OVERALL_LENGTH as f64) as u64;
+
+        if let Some(overall) = self.overall.as_mut() {
+            if position > self.position {
+                self.position = position;
+                overall.set_position(position);
+            }
+        }
+    }
+}
+
+// Called with the bytes read from the source so far and the content length, 0 if it is unknown,
+// so a library user can drive their own progress display instead of the indicators
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+}
+
+// Passes the bytes of the source through, calling the progress callback at most once every
+// UPDATE_INTERVAL and once more when the source ends
+pub struct ProgressReader<R> {
+    inner: R,
+    callback: Option<ProgressCallback>,
+    length: u64,
+    bytes_read: u64,
+    last_report: Option<Instant>,
+    finished: bool,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, callback: Option<ProgressCallback>, length: u64) -> Self {
+        ProgressReader {
+            inner,
+            callback,
+            length,
+            bytes_read: 0,
+            last_report: None,
+            finished: false,
+        }
+    }
+}
+
+impl<R> AsyncRead for ProgressReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled: usize = buf.filled().len();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        if let (Poll::Ready(Ok(())), Some(callback)) = (&poll, &this.callback) {
+            let read: usize = buf.filled().len() - filled;
+            this.bytes_read += read as u64;
+
+            // A read that adds nothing to a buffer with room in it is the end of the source
+            let ended: bool = read == 0 && buf.remaining() > 0;
+            let due: bool = this.last_report.is_none_or(|last_report| last_report.elapsed() >= UPDATE_INTERVAL);
+
+            if (ended && !this.finished) || (!ended && due) {
+                (callback.0)(this.bytes_read, this.length);
+                this.last_report = Some(Instant::now());
+                this.finished = ended;
+            }
+        }
+
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_rapid_updates() {
+        let mut progress: Progress = Progress::new(ProgressMode::Plain, 100, "Testing", ProgressUnits::Percent);
+
+        // The first update is shown, the rest arrive within the interval and are held back
+        for position in 0..50 {
+            progress.set_position(position);
+        }
+
+        assert_eq!(progress.pending, Some(49));
+
+        // Finishing shows the last position held back
+        progress.finish();
+        assert_eq!(progress.pending, None);
+    }
+
+    #[test]
+    fn the_download_share_shrinks_as_the_inserts_grow() {
+        let content_length: u64 = 100 * 1024 * 1024;
+        let share: f64 = download_share(content_length, 0);
+        assert!(share > 0.5 && share < 1.0);
+
+        // A resumed run skips most of the inserts, so the download is most of the work
+        let resumed: f64 = download_share(content_length, content_length / 250 * 9 / 10);
+        assert!(resumed > share);
+        assert_eq!(download_share(0, 0), DEFAULT_DOWNLOAD_SHARE);
+    }
+
+    #[test]
+    fn the_overall_position_spans_both_phases() {
+        let mut progress: RunProgress = RunProgress::new(ProgressMode::Plain, false, true);
+        progress.start_download(1000, 0);
+        let share: f64 = progress.download_share;
+
+        progress.set_download_position(500);
+        assert_eq!(progress.position, (share * 0.5 * OVERALL_LENGTH as f64) as u64);
+
+        progress.finish_download();
+        assert_eq!(progress.position, (share * OVERALL_LENGTH as f64) as u64);
+
+        // The insert percentage covers the rest of the run
+        progress.start_inserts();
+        progress.set_insert_percent(100.0);
+        assert_eq!(progress.position, OVERALL_LENGTH);
+
+        progress.finish();
+        assert!(progress.overall.is_none());
+    }
+
+    #[test]
+    fn verbose_progress_shows_each_phase() {
+        let mut progress: RunProgress = RunProgress::new(ProgressMode::Plain, true, true);
+        progress.start_download(1000, 0);
+        assert!(progress.phase.is_some() && progress.overall.is_none());
+
+        progress.finish_download();
+        assert!(progress.phase.is_none());
+
+        progress.start_inserts();
+        assert!(progress.phase.is_some());
+    }
+
+    #[tokio::test]
+    async fn the_callback_hears_the_first_read_and_the_end() {
+        use std::sync::Mutex;
+
+        use tokio::io::AsyncReadExt;
+
+        let calls: Arc<Mutex<Vec<(u64, u64)>>> = Arc::new(Mutex::new(Vec::new()));
+        let recorded: Arc<Mutex<Vec<(u64, u64)>>> = calls.clone();
+        let callback: ProgressCallback = ProgressCallback::new(move |done, total| recorded.lock().unwrap().push((done, total)));
+
+        // Small reads all land within the interval, so only the first and the end are reported
+        let mut reader = ProgressReader::new(std::io::Cursor::new(vec![b'x'; 100]), Some(callback), 100);
+        let mut buffer = [0u8; 10];
+
+        while reader.read(&mut buffer).await.unwrap() > 0 {}
+        assert_eq!(reader.read(&mut buffer).await.unwrap(), 0);
+
+        assert_eq!(*calls.lock().unwrap(), [(10, 100), (100, 100)]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use csv_async::Terminator;
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Wraps the source so the bytes read by the CSV reader are also kept for --keep-raw
+pub struct RecordingReader<R> {
+    inner: R,
+    recorded: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+// The source bytes that have been read but not yet claimed by a record
+//
+// The CSV reader reads ahead, so the buffer holds the current record and whatever follows it,
+// and each record drains everything before its end
+pub struct RawLines {
+    recorded: Arc<Mutex<Vec<u8>>>,
+    offset: u64,
+    terminator: Terminator,
+}
+
+impl<R> RecordingReader<R> {
+    // Wrap a reader, recording its bytes only if the raw lines are wanted
+    pub fn new(inner: R, terminator: Option<Terminator>) -> (Self, Option<RawLines>) {
+        let recorded: Option<Arc<Mutex<Vec<u8>>>> = terminator.map(|_| Arc::new(Mutex::new(Vec::new())));
+
+        let raw_lines: Option<RawLines> = recorded.clone().zip(terminator).map(|(recorded, terminator)| RawLines {
+            recorded,
+            offset: 0,
+            terminator,
+        });
+
+        (RecordingReader { inner, recorded }, raw_lines)
+    }
+}
+
+impl<R> AsyncRead for RecordingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled: usize = buf.filled().len();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        // Copy whatever this read added to the buffer
+        if let (Poll::Ready(Ok(())), Some(recorded)) = (&poll, &this.recorded) {
+            let mut recorded = recorded.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            recorded.extend_from_slice(&buf.filled()[filled..]);
+        }
+
+        poll
+    }
+}
+
+impl RawLines {
+    // The source text between two byte positions of the CSV reader, without the record terminator
+    //
+    // Bytes that aren't valid UTF-8 are replaced with U+FFFD
+    pub fn take(&mut self, start: u64, end: u64) -> String {
+        let mut recorded = self.recorded.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let from: usize = (start.saturating_sub(self.offset) as usize).min(recorded.len());
+        let to: usize = (end.saturating_sub(self.offset) as usize).clamp(from, recorded.len());
+
+        // With CRLF, the reader ends a record at the \r and skips the \n at the start of the next
+        let is_terminator = |byte: &u8| match self.terminator {
+            Terminator::CRLF => *byte == b'\r' || *byte == b'\n',
+            Terminator::Any(terminator) => *byte == terminator,
+            _ => false,
+        };
+
+        let mut line: &[u8] = &recorded[from..to];
+
+        while let [first, rest @ ..] = line {
+            match is_terminator(first) {
+                true => line = rest,
+                false => break,
+            }
+        }
+
+        while let [rest @ .., last] = line {
+            match is_terminator(last) {
+                true => line = rest,
+                false => break,
+            }
+        }
+
+        let raw: String = String::from_utf8_lossy(line).into_owned();
+
+        // Nothing before the end of this record is needed again
+        recorded.drain(..to);
+        self.offset += to as u64;
+
+        raw
+    }
+}
@@ -1,53 +1,97 @@
-use reqwest::{Client, ClientBuilder, Response};
+use std::collections::HashMap;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use tokio::io::AsyncRead;
+use reqwest::header::{HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Client, ClientBuilder, Response, StatusCode, Url};
+
+use tempfile::NamedTempFile;
+
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt, ReadBuf};
 use tokio::sync::mpsc;
 use tokio::task;
+use tokio_util::bytes::Bytes;
 use tokio_util::io::StreamReader;
 
-use futures::stream::{StreamExt, TryStreamExt};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 
 use serde::de::DeserializeOwned;
 
-use csv_async::{self, DeserializeRecordsStreamPos};
+use clap::ValueEnum;
 
-// Errors that can occur
-pub enum DownloadError<D>
-where
-    D: DeserializeOwned + Send + Sync + 'static,
-{
-    ReqwestError(reqwest::Error),
-    CsvError(csv_async::Error),
-    SendError(mpsc::error::SendError<RecordInfo<D>>),
-    ZeroLengthError,
-    ChannelError,
-}
+use csv_async::{self, AsyncDeserializer, StringRecord, Terminator};
 
-impl<D> From<reqwest::Error> for DownloadError<D>
-where
-    D: DeserializeOwned + Send + Sync + 'static,
-{
-    fn from(error: reqwest::Error) -> Self {
-        DownloadError::ReqwestError(error)
-    }
-}
+use tracing::{debug, info};
 
-impl<D> From<csv_async::Error> for DownloadError<D>
-where
-    D: DeserializeOwned + Send + Sync + 'static,
-{
-    fn from(error: csv_async::Error) -> Self {
-        DownloadError::CsvError(error)
-    }
+use crate::checksum::{ChecksumAlgorithm, ChecksumReader};
+use crate::csv_dialect::CsvDialect;
+use crate::models::{FilterMap, SchemaDrift};
+use crate::memory::{MemoryBudget, MemoryPermit};
+use crate::pause::Pause;
+use crate::headers::DEFAULT_USER_AGENT;
+use crate::proxy::ProxySetting;
+use crate::progress::{Progress, ProgressCallback, ProgressMode, ProgressReader, ProgressUnits, UPDATE_INTERVAL};
+use crate::raw_lines::{RawLines, RecordingReader};
+use crate::report;
+use crate::signing::RequestSigner;
+
+// The number of parsed records that can wait for the consumer before the reader pauses
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 4096;
+
+// How long a request can wait for the server to send anything before it fails, long enough for a
+// slow server but not so long that a dead connection hangs the run
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(300);
+
+// The wait before the first retry of a failed request, doubling after each attempt
+pub const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+// What happens to a record that fails to parse
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Stop the download at the first record that fails to parse
+    #[default]
+    Abort,
+
+    /// Skip the record with a warning and carry on with the next
+    Skip,
 }
 
-impl<D> From<mpsc::error::SendError<RecordInfo<D>>> for DownloadError<D>
+// Errors that can occur, the underlying reqwest, CSV, channel and I/O errors are kept as the source
+#[allow(clippy::enum_variant_names)]
+#[derive(thiserror::Error)]
+pub enum DownloadError<D>
 where
     D: DeserializeOwned + Send + Sync + 'static,
 {
-    fn from(error: mpsc::error::SendError<RecordInfo<D>>) -> Self {
-        DownloadError::SendError(error)
-    }
+    #[error("{}", match .0.is_timeout() { true => "Timed out waiting for the server", false => "Reqwest error" })]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("CSV error")]
+    CsvError(#[from] csv_async::Error),
+    #[error("Send error")]
+    SendError(#[from] mpsc::error::SendError<RecordInfo<D>>),
+    #[error("The content length is zero")]
+    ZeroLengthError,
+    #[error("Channel error")]
+    ChannelError,
+    #[error("Header error: {0}")]
+    HeaderError(String),
+    #[error("S3 error: {0}")]
+    S3Error(String),
+    #[error("No data received for {0:?}")]
+    StallError(Duration),
+    #[error("Aborted after {0} record errors, the source looks malformed")]
+    TooManyErrors(u64),
+    #[error("Range download error: {0}")]
+    RangeError(String),
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+    #[error("Signing error: {0}")]
+    SigningError(String),
 }
 
 impl<D> From<DownloadError<D>> for std::io::Error
@@ -55,51 +99,85 @@ where
     D: DeserializeOwned + Send + Sync + 'static,
 {
     fn from(error: DownloadError<D>) -> Self {
-        std::io::Error::new(std::io::ErrorKind::Other, error)
-    }
-}
-
-impl<D> std::fmt::Display for DownloadError<D>
-where
-    D: DeserializeOwned + Send + Sync + 'static,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            DownloadError::ReqwestError(e) => write!(f, "Reqwest error: {}", e),
-            DownloadError::CsvError(e) => write!(f, "CSV error: {}", e),
-            DownloadError::SendError(e) => write!(f, "Send error: {}", e),
-            DownloadError::ZeroLengthError => write!(f, "The content length is zero"),
-            DownloadError::ChannelError => write!(f, "Channel error"),
-        }
+        std::io::Error::other(error)
     }
 }
 
+// The record in a send error isn't Debug, so this is written out rather than derived
 impl<D> std::fmt::Debug for DownloadError<D>
 where
     D: DeserializeOwned + Send + Sync + 'static,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            DownloadError::ReqwestError(e) => write!(f, "Reqwest error: {}", e),
-            DownloadError::CsvError(e) => write!(f, "CSV error: {}", e),
-            DownloadError::SendError(e) => write!(f, "Send error: {}", e),
-            DownloadError::ZeroLengthError => write!(f, "The content length is zero"),
-            DownloadError::ChannelError => write!(f, "Channel error"),
+            DownloadError::ReqwestError(e) => f.debug_tuple("ReqwestError").field(e).finish(),
+            DownloadError::CsvError(e) => f.debug_tuple("CsvError").field(e).finish(),
+            DownloadError::SendError(e) => f.debug_tuple("SendError").field(e).finish(),
+            DownloadError::ZeroLengthError => write!(f, "ZeroLengthError"),
+            DownloadError::ChannelError => write!(f, "ChannelError"),
+            DownloadError::HeaderError(e) => f.debug_tuple("HeaderError").field(e).finish(),
+            DownloadError::S3Error(e) => f.debug_tuple("S3Error").field(e).finish(),
+            DownloadError::StallError(timeout) => f.debug_tuple("StallError").field(timeout).finish(),
+            DownloadError::TooManyErrors(errors) => f.debug_tuple("TooManyErrors").field(errors).finish(),
+            DownloadError::RangeError(e) => f.debug_tuple("RangeError").field(e).finish(),
+            DownloadError::IoError(e) => f.debug_tuple("IoError").field(e).finish(),
+            DownloadError::SigningError(e) => f.debug_tuple("SigningError").field(e).finish(),
         }
     }
 }
 
-impl<D> std::error::Error for DownloadError<D> where D: DeserializeOwned + Send + Sync + 'static {}
-
 pub struct DownloadInfo<D> {
     pub content_length: u64,
-    pub rx_channel: mpsc::UnboundedReceiver<RecordInfo<D>>,
-    tx_channel: Option<mpsc::UnboundedSender<RecordInfo<D>>>,
+    pub rx_channel: mpsc::Receiver<RecordInfo<D>>,
+    tx_channel: Option<mpsc::Sender<RecordInfo<D>>>,
+    column_renames: HashMap<String, String>,
+    stall_timeout: Option<Duration>,
+    csv_dialect: CsvDialect,
+    keep_raw: bool,
+    on_error: OnError,
+    max_errors: Option<u64>,
+    record_errors: Arc<AtomicU64>,
+    quote_fallback: bool,
+    recovered_records: Arc<AtomicU64>,
+    bytes_read: Arc<AtomicU64>,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    checksum: Arc<Mutex<Option<String>>>,
+    download_connections: usize,
+    max_reconnects: u32,
+    max_attempts: u32,
+    retry_delay: Duration,
+    pause: Option<Pause>,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    progress_mode: Option<ProgressMode>,
+    progress_callback: Option<ProgressCallback>,
+    request_signer: Option<RequestSigner>,
+    proxy: ProxySetting,
+    request_headers: HeaderMap,
+    http_timeout: Duration,
+}
+
+// Details of a remote file, read from its headers without downloading the body
+pub struct RemoteFileInfo {
+    pub content_length: Option<u64>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
 }
 
 pub struct RecordInfo<D> {
     pub record: D,
     pub position: u64,
+    pub raw: Option<String>,
+    // The record's share of --memory-budget, held until it has been stored
+    pub memory: Option<MemoryPermit>,
+}
+
+impl<D> Default for DownloadInfo<D>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<D> DownloadInfo<D>
@@ -107,89 +185,1733 @@ where
     D: DeserializeOwned + Send + Sync + 'static,
 {
     pub fn new() -> Self {
-        // Create a tokio channel to send records to
-        let (tx, rx) = mpsc::unbounded_channel::<RecordInfo<D>>();
+        Self::with_capacity(DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        // Create a tokio channel to send records to, the reader waits while it is full so a slow
+        // consumer bounds the records held in memory whatever the download speed
+        let (tx, rx) = mpsc::channel::<RecordInfo<D>>(capacity);
 
         DownloadInfo {
             content_length: 0,
             rx_channel: rx,
             tx_channel: Some(tx),
+            column_renames: HashMap::new(),
+            stall_timeout: None,
+            csv_dialect: CsvDialect::default(),
+            keep_raw: false,
+            on_error: OnError::Abort,
+            max_errors: None,
+            record_errors: Arc::new(AtomicU64::new(0)),
+            quote_fallback: false,
+            recovered_records: Arc::new(AtomicU64::new(0)),
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            checksum_algorithm: None,
+            checksum: Arc::new(Mutex::new(None)),
+            download_connections: 1,
+            max_reconnects: 0,
+            max_attempts: 1,
+            retry_delay: RETRY_BASE_DELAY,
+            pause: None,
+            memory_budget: None,
+            progress_mode: None,
+            progress_callback: None,
+            request_signer: None,
+            proxy: ProxySetting::default(),
+            request_headers: HeaderMap::new(),
+            http_timeout: DEFAULT_HTTP_TIMEOUT,
         }
     }
 
+    pub fn set_column_renames(&mut self, column_renames: HashMap<String, String>) {
+        // Map of CSV column names to the model field names they should be read as
+        self.column_renames = column_renames;
+    }
+
+    pub fn set_csv_dialect(&mut self, csv_dialect: CsvDialect) {
+        // Read the CSV with these delimiter, quote, terminator and escape settings
+        self.csv_dialect = csv_dialect;
+    }
+
+    pub fn set_keep_raw(&mut self, keep_raw: bool) {
+        // Send each record's source line along with the record
+        self.keep_raw = keep_raw;
+    }
+
+    pub fn set_on_error(&mut self, on_error: OnError) {
+        // Skip the records that fail to parse rather than stopping at the first
+        self.on_error = on_error;
+    }
+
+    pub fn set_max_errors(&mut self, max_errors: Option<u64>) {
+        // Skip records that fail to parse, aborting once this many have been skipped
+        self.max_errors = max_errors;
+    }
+
+    pub fn record_errors(&self) -> u64 {
+        // The number of records skipped because they failed to parse
+        self.record_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn set_quote_fallback(&mut self, quote_fallback: bool) {
+        // Read a record that fails to parse again with its quotes as ordinary characters before rejecting it
+        self.quote_fallback = quote_fallback;
+    }
+
+    pub fn recovered_records(&self) -> u64 {
+        // The number of records read by the quote fallback after failing to parse
+        self.recovered_records.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        // The number of bytes the CSV reader had consumed when the source ended
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn set_checksum_algorithm(&mut self, checksum_algorithm: Option<ChecksumAlgorithm>) {
+        // Hash the source bytes with this algorithm as they are read
+        self.checksum_algorithm = checksum_algorithm;
+    }
+
+    pub fn checksum(&self) -> Option<String> {
+        // The hex digest of the source, once it has been read to the end
+        self.checksum.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn set_download_connections(&mut self, download_connections: usize) {
+        // Download in this many parallel byte ranges when the server supports them
+        self.download_connections = download_connections;
+    }
+
+    pub fn set_max_reconnects(&mut self, max_reconnects: u32) {
+        // Resume an interrupted single connection download from where it stopped, up to this many times
+        self.max_reconnects = max_reconnects;
+    }
+
+    pub fn set_max_attempts(&mut self, max_attempts: u32) {
+        // Send the first request up to this many times if it fails with a connection error or a 5xx status
+        self.max_attempts = max_attempts.max(1);
+    }
+
+    pub fn set_pause(&mut self, pause: Pause) {
+        // Stop reading the source while the download is paused
+        self.pause = Some(pause);
+    }
+
+    pub fn set_request_signer(&mut self, request_signer: Option<RequestSigner>) {
+        // Send an HMAC signature of the path with each request, for mirrors that need one
+        self.request_signer = request_signer;
+    }
+
+    pub fn set_proxy(&mut self, proxy: ProxySetting) {
+        // Send every request, including the range and resume requests, through the proxy
+        self.proxy = proxy;
+    }
+
+    pub fn set_request_headers(&mut self, request_headers: HeaderMap) {
+        // Send these headers with every request, replacing the default User-Agent if one is given
+        self.request_headers = request_headers;
+    }
+
+    pub fn set_http_timeout(&mut self, http_timeout: Duration) {
+        // Fail a request, or a download already under way, if the server sends nothing for this long
+        self.http_timeout = http_timeout;
+    }
+
+    pub fn set_memory_budget(&mut self, memory_budget: Arc<MemoryBudget>) {
+        // Wait before sending a record while the records already read use up the budget
+        self.memory_budget = Some(memory_budget);
+    }
+
+    pub fn memory_budget(&self) -> Option<Arc<MemoryBudget>> {
+        self.memory_budget.clone()
+    }
+
+    pub fn set_progress_mode(&mut self, progress_mode: ProgressMode) {
+        // Show the progress of downloads that finish before the records are read, e.g. parallel ranges
+        self.progress_mode = Some(progress_mode);
+    }
+
+    pub fn set_progress_callback(&mut self, progress_callback: ProgressCallback) {
+        // Report the bytes read from the source and the content length as the records are read
+        self.progress_callback = Some(progress_callback);
+    }
+
+    pub fn set_stall_timeout(&mut self, stall_timeout: Option<Duration>) {
+        // Fail the download if no data arrives for this long, the timer restarts each time bytes arrive
+        self.stall_timeout = stall_timeout;
+    }
+
+    // Download the URL, calling the callback with the bytes read and the content length as it goes
+    pub async fn download_with_progress(
+        &mut self,
+        url: &str,
+        callback: impl Fn(u64, u64) + Send + Sync + 'static,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        self.set_progress_callback(ProgressCallback::new(callback));
+        self.download(url).await
+    }
+
     pub async fn download(
         &mut self,
         url: &str,
     ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        // Read a file:// URL straight from disk
+        if let Some(path) = file_url_path(url) {
+            return self.download_file(&path).await;
+        }
+
+        // Create a reqwest client
+        let http_client: Client = self.http_client(url)?;
+
+        // Use parallel ranges if requested and the server supports them, otherwise a single connection
+        if self.download_connections > 1 {
+            if let Some(content_length) = ranges_supported(&http_client, url).await {
+                return self.download_ranges(&http_client, url, content_length).await;
+            }
+        }
+
+        // Send a GET request to the URL, retrying transient failures
+        let response: Response = send_with_retries::<D>(&http_client, url, self.max_attempts, self.retry_delay).await?;
+
+        // Get the content length, which a chunked response doesn't send, zero is then read as unknown
+        self.content_length = match response.content_length() {
+            Some(0) => return Err(DownloadError::ZeroLengthError),
+            Some(content_length) => content_length,
+            None => 0,
+        };
+
+        // Get the response as a stream of bytes, reconnecting if it is interrupted
+        let bytes_stream = with_reconnects::<D>(http_client, url, response, self.content_length, self.max_reconnects);
+
+        // Convert the stream of bytes to an AsyncRead and parse it
+        match self.stall_timeout {
+            Some(stall_timeout) => {
+                self.download_from_reader(StreamReader::new(with_stall_timeout(bytes_stream, stall_timeout)))
+                    .await
+            }
+            None => self.download_from_reader(StreamReader::new(bytes_stream)).await,
+        }
+    }
+
+    async fn download_ranges(
+        &mut self,
+        http_client: &Client,
+        url: &str,
+        content_length: u64,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        // Reserve a temporary file the size of the download, it is deleted once it has been parsed
+        let temp_file: NamedTempFile = NamedTempFile::new()?;
+        temp_file.as_file().set_len(content_length)?;
+
+        // Split the file into one range per connection
+        let range_length: u64 = content_length.div_ceil(self.download_connections as u64);
+
+        // The ranges add the bytes they write, for the progress indicator
+        let downloaded: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+        let progress_handle: Option<task::JoinHandle<()>> = self
+            .progress_mode
+            .map(|progress_mode| tokio::spawn(show_range_progress(progress_mode, content_length, downloaded.clone())));
+
+        let join_handles: Vec<task::JoinHandle<Result<(), DownloadError<D>>>> = (0..content_length)
+            .step_by(range_length as usize)
+            .map(|start| {
+                let end: u64 = (start + range_length).min(content_length) - 1;
+
+                tokio::spawn(download_range(
+                    http_client.clone(),
+                    url.to_string(),
+                    temp_file.path().to_path_buf(),
+                    start,
+                    end,
+                    self.stall_timeout,
+                    downloaded.clone(),
+                ))
+            })
+            .collect();
+
+        // Wait for every range, the file is only complete if they all succeed
+        let mut result: Result<(), DownloadError<D>> = Ok(());
+
+        for join_handle in join_handles {
+            let range_result: Result<(), DownloadError<D>> = join_handle
+                .await
+                .unwrap_or_else(|error| Err(DownloadError::RangeError(error.to_string())));
+
+            if let (Err(error), Ok(_)) = (range_result, &result) {
+                result = Err(error);
+            }
+        }
+
+        // Stop the progress indicator once every range has finished, then report the first failure
+        if let Some(progress_handle) = progress_handle {
+            downloaded.store(u64::MAX, Ordering::Relaxed);
+            let _ = progress_handle.await;
+        }
+
+        result?;
+
+        self.content_length = content_length;
+
+        // Parse the reassembled file
+        let file: tokio::fs::File = tokio::fs::File::open(temp_file.path()).await?;
+        self.download_from_reader(TempFileReader { file, temp_file }).await
+    }
+
+    pub async fn estimate(&self, url: &str) -> Result<RemoteFileInfo, DownloadError<D>> {
         // Create a reqwest client
-        let http_client: Client = ClientBuilder::new().build()?;
+        let http_client: Client = self.http_client(url)?;
+
+        // Ask for the headers only
+        let head_response: Option<Response> = match http_client.head(url).send().await {
+            Ok(response) if response.status().is_success() => Some(response),
+            _ => None,
+        };
+
+        match head_response {
+            Some(response) => {
+                let headers: &HeaderMap = response.headers();
+
+                Ok(RemoteFileInfo {
+                    content_length: header_value(headers, CONTENT_LENGTH).and_then(|value| value.parse().ok()),
+                    last_modified: header_value(headers, LAST_MODIFIED),
+                    content_type: header_value(headers, CONTENT_TYPE),
+                })
+            }
+            None => {
+                // The server doesn't support HEAD, request the first byte instead
+                let response: Response = http_client
+                    .get(url)
+                    .header(RANGE, "bytes=0-0")
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                let headers: &HeaderMap = response.headers();
+
+                // A ranged response gives the total size after the slash, e.g. "bytes 0-0/12345",
+                // if the range was ignored the content length is the full size
+                let content_length: Option<u64> = match header_value(headers, CONTENT_RANGE) {
+                    Some(range) => range.rsplit_once('/').and_then(|(_, total)| total.parse().ok()),
+                    None => header_value(headers, CONTENT_LENGTH).and_then(|value| value.parse().ok()),
+                };
+
+                Ok(RemoteFileInfo {
+                    content_length,
+                    last_modified: header_value(headers, LAST_MODIFIED),
+                    content_type: header_value(headers, CONTENT_TYPE),
+                })
+            }
+        }
+    }
+
+    // Read a small text file, such as a checksum sidecar, from a URL or a local path
+    pub async fn fetch_text(&self, location: &str) -> Result<String, DownloadError<D>> {
+        let path: Option<PathBuf> = match location.contains("://") {
+            true => file_url_path(location),
+            false => Some(PathBuf::from(location)),
+        };
+
+        if let Some(path) = path {
+            return Ok(tokio::fs::read_to_string(path).await?);
+        }
+
+        let http_client: Client = self.http_client(location)?;
+        let response: Response = send_with_retries::<D>(&http_client, location, self.max_attempts, self.retry_delay).await?;
+
+        Ok(response.text().await?)
+    }
+
+    // A client for requests to this URL, which sends the signature header with each if requests are signed
+    fn http_client(&self, url: &str) -> Result<Client, DownloadError<D>> {
+        let mut client_builder: ClientBuilder = self
+            .proxy
+            .apply(ClientBuilder::new())?
+            .read_timeout(self.http_timeout)
+            .user_agent(DEFAULT_USER_AGENT)
+            .default_headers(self.request_headers.clone());
+
+        if let Some(request_signer) = &self.request_signer {
+            client_builder = client_builder.default_headers(request_signer.headers(url).map_err(DownloadError::SigningError)?);
+        }
+
+        Ok(client_builder.build()?)
+    }
+
+    pub async fn download_file(
+        &mut self,
+        path: &Path,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        // Open the file, using its size as the content length for the progress and length check
+        let file: tokio::fs::File = tokio::fs::File::open(path).await?;
+        self.content_length = file.metadata().await?.len();
+
+        // Parse it exactly as a downloaded body
+        self.download_from_reader(file).await
+    }
+
+    #[cfg(feature = "s3")]
+    pub async fn download_s3(
+        &mut self,
+        url: &str,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        // Split the URL into the bucket and key
+        let (bucket, key) = url
+            .strip_prefix("s3://")
+            .and_then(|path| path.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| DownloadError::S3Error(format!("{} is not of the form s3://bucket/key", url)))?;
+
+        // Create a client using the standard AWS credential chain
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let s3_client = aws_sdk_s3::Client::new(&config);
+
+        // Get the content length from the object's metadata
+        let head = s3_client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|error| s3_error(url, error))?;
+
+        self.content_length = head.content_length().unwrap_or_default().max(0) as u64;
 
-        // Send a GET request to the URL
-        let response: Response = http_client.get(url).send().await?.error_for_status()?;
+        // Get the object and stream its body into the CSV reader
+        let object = s3_client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|error| s3_error(url, error))?;
 
-        // Get the content length
-        self.content_length = response.content_length().ok_or(DownloadError::ZeroLengthError)?;
+        self.download_from_reader(object.body.into_async_read()).await
+    }
+
+    #[cfg(not(feature = "s3"))]
+    pub async fn download_s3(
+        &mut self,
+        url: &str,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>> {
+        Err(DownloadError::S3Error(format!(
+            "cannot download {}, rebuild with --features s3 to enable S3 support",
+            url
+        )))
+    }
 
+    pub async fn download_from_reader<R>(
+        &mut self,
+        reader: R,
+    ) -> Result<task::JoinHandle<Result<(), DownloadError<D>>>, DownloadError<D>>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
         // Clone the tx_channel, or return an error
         let tx_channel = self.tx_channel.clone().ok_or(DownloadError::ChannelError)?;
 
         // Set the tx_channel in the struct to None to drop it, the clone is used in the task and will be dropped when the task is done
         self.tx_channel = None;
 
-        // Spawn a tokio task to iterate over the records
-        let join_handle = tokio::spawn(async move {
-            // Get the response as a stream of bytes
-            let bytes_stream = response
-                .bytes_stream()
-                .map_err(DownloadError::<D>::ReqwestError);
+        // Report the bytes read to the progress callback, if there is one
+        let reader = ProgressReader::new(reader, self.progress_callback.clone(), self.content_length);
+
+        // Create a CSV reader
+        // let mut csv_reader = csv_async::AsyncDeserializer::from_reader(reader);
+        // Hash the source bytes if a checksum is wanted
+        let (reader, checksum_handle) = ChecksumReader::new(reader, self.checksum_algorithm);
 
-            // Convert the stream of bytes to an AsyncRead
-            let stream_reader = StreamReader::new(bytes_stream);
+        // Record the source bytes if the raw lines are kept, or the quote fallback needs them to read a record again
+        let record_source: bool = self.keep_raw || self.quote_fallback;
+        let (reader, raw_lines) = RecordingReader::new(reader, record_source.then_some(self.csv_dialect.terminator));
+        let mut csv_reader = self.csv_dialect.builder().create_deserializer(reader);
 
-            // Create a CSV reader
-            // let mut csv_reader = csv_async::AsyncDeserializer::from_reader(stream_reader);
-            let mut csv_reader = csv_async::AsyncReaderBuilder::new()
-                .quote(b'\'')
-                .create_deserializer(stream_reader);
+        // Read and check the header before any records are processed
+        self.prepare_headers(&mut csv_reader).await?;
 
-            // Create a deserializer
-            let mut records = csv_reader.deserialize_with_pos::<D>();
+        // Count the record errors where they can be read after the task
+        let error_policy: ErrorPolicy = ErrorPolicy {
+            on_error: self.on_error,
+            max_errors: self.max_errors,
+            record_errors: self.record_errors.clone(),
+            quote_fallback: self.quote_fallback.then_some(self.csv_dialect),
+            recovered_records: self.recovered_records.clone(),
+        };
+        let keep_raw: bool = self.keep_raw;
+        let bytes_read: Arc<AtomicU64> = self.bytes_read.clone();
+        let checksum: Arc<Mutex<Option<String>>> = self.checksum.clone();
+        let pause: Option<Pause> = self.pause.clone();
+        let memory_budget: Option<Arc<MemoryBudget>> = self.memory_budget.clone();
 
+        // Spawn a tokio task to iterate over the records
+        let join_handle = tokio::spawn(async move {
             // Iterate over the records
-            iterate_records(&mut records, tx_channel).await?;
+            iterate_records(&mut csv_reader, tx_channel, raw_lines, keep_raw, &error_policy, pause, memory_budget).await?;
+
+            // The reader is at the end of the source, to compare with the content length
+            bytes_read.store(csv_reader.position().byte(), Ordering::Relaxed);
+
+            // Every byte of the source has been hashed
+            if let Some(checksum_handle) = checksum_handle {
+                *checksum.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = checksum_handle.finish();
+            }
 
             // Return Ok
             Ok(())
         });
 
-        // Return the content length
-        return Ok(join_handle);
+        // Return the join handle
+        Ok(join_handle)
+    }
+
+    pub async fn download_columns(&self, url: &str) -> Result<Vec<String>, DownloadError<D>> {
+        let response: Response = self.http_client(url)?.get(url).send().await?.error_for_status()?;
+
+        let bytes_stream = response
+            .bytes_stream()
+            .map_err(DownloadError::<D>::ReqwestError);
+
+        // Only the header is read, the rest of the download is dropped with the reader
+        self.read_columns(StreamReader::new(bytes_stream)).await
+    }
+
+    pub async fn read_columns<R>(&self, reader: R) -> Result<Vec<String>, DownloadError<D>>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let mut csv_reader = self.csv_dialect.builder().create_deserializer(reader);
+        let headers: StringRecord = self.renamed_headers(&mut csv_reader).await?;
+
+        Ok(headers.iter().map(str::to_string).collect())
+    }
+
+    async fn renamed_headers<R>(&self, csv_reader: &mut AsyncDeserializer<R>) -> Result<StringRecord, DownloadError<D>>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        // Apply the column renames to the header
+        Ok(csv_reader
+            .headers()
+            .await?
+            .iter()
+            .map(|column| match self.column_renames.get(column) {
+                Some(field) => field.as_str(),
+                None => column,
+            })
+            .collect())
+    }
+
+    async fn prepare_headers<R>(
+        &self,
+        csv_reader: &mut AsyncDeserializer<R>,
+    ) -> Result<(), DownloadError<D>>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let headers: StringRecord = self.renamed_headers(csv_reader).await?;
+
+        // Every model field must be covered by a column, otherwise it would silently be left empty
+        let missing: Vec<&str> = SchemaDrift::new::<D>(headers.iter()).missing;
+
+        if !missing.is_empty() {
+            return Err(DownloadError::HeaderError(format!(
+                "no column for field(s) {}, add a [columns] override to the config file",
+                missing.join(", ")
+            )));
+        }
+
+        csv_reader.set_headers(headers);
+
+        Ok(())
+    }
+}
+
+// Where a record stream has got to
+enum StreamState<D>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    // Not polled yet, the download starts with the first poll
+    Start(DownloadInfo<D>, String),
+    Reading(DownloadInfo<D>, task::JoinHandle<Result<(), DownloadError<D>>>),
+    Done,
+}
+
+impl<D> DownloadInfo<D>
+where
+    D: DeserializeOwned + FilterMap + Send + Sync + 'static,
+{
+    // Stream the records of the URL with the settings made so far, filtered and mapped as they would be stored
+    //
+    // Nothing is downloaded until the stream is first polled. The reader stops while the channel is
+    // full, so a consumer that stops polling holds the download back rather than buffering the file.
+    // A download error ends the stream after the records read before it
+    pub fn stream(self, url: &str) -> impl Stream<Item = Result<D, DownloadError<D>>> {
+        stream::unfold(StreamState::Start(self, url.to_string()), |state| async move {
+            let (mut download_info, join_handle) = match state {
+                StreamState::Start(mut download_info, url) => match download_info.download(&url).await {
+                    Ok(join_handle) => (download_info, join_handle),
+                    Err(error) => return Some((Err(error), StreamState::Done)),
+                },
+                StreamState::Reading(download_info, join_handle) => (download_info, join_handle),
+                StreamState::Done => return None,
+            };
+
+            // Skip the records that wouldn't be stored
+            while let Some(record_info) = download_info.rx_channel.recv().await {
+                if record_info.record.filter() {
+                    return Some((Ok(record_info.record.map()), StreamState::Reading(download_info, join_handle)));
+                }
+            }
+
+            // The channel closes when the reader stops, report why if it failed
+            match join_handle.await {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some((Err(error), StreamState::Done)),
+                Err(error) => Some((Err(std::io::Error::from(error).into()), StreamState::Done)),
+            }
+        })
     }
 }
 
-async fn iterate_records<'r, R, D>(
-    records: &mut DeserializeRecordsStreamPos<'r, R, D>,
-    tx_channel: mpsc::UnboundedSender<RecordInfo<D>>,
+// The content length if the server accepts byte ranges for the URL
+async fn ranges_supported(http_client: &Client, url: &str) -> Option<u64> {
+    let response: Response = http_client.head(url).send().await.ok()?;
+
+    if !response.status().is_success() || header_value(response.headers(), ACCEPT_RANGES).as_deref() != Some("bytes") {
+        return None;
+    }
+
+    header_value(response.headers(), CONTENT_LENGTH)
+        .and_then(|value| value.parse().ok())
+        .filter(|content_length| *content_length > 0)
+}
+
+// Download the inclusive byte range start..=end into the same range of the file at path
+async fn download_range<D>(
+    http_client: Client,
+    url: String,
+    path: PathBuf,
+    start: u64,
+    end: u64,
+    stall_timeout: Option<Duration>,
+    downloaded: Arc<AtomicU64>,
 ) -> Result<(), DownloadError<D>>
 where
-    R: AsyncRead + Send + Unpin,
     D: DeserializeOwned + Send + Sync + 'static,
 {
-    // Iterate over the records
-    while let Some((record, pos)) = records.next().await {
-        // Get the record
-        let record = record?;
+    debug!(start, end, "Requesting a range of the file");
+
+    let response: Response = http_client
+        .get(&url)
+        .header(RANGE, format!("bytes={}-{}", start, end))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    // A 200 would be the whole file, which can't be written into the range
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::RangeError(format!(
+            "expected a partial response for bytes {}-{}, got {}",
+            start,
+            end,
+            response.status()
+        )));
+    }
+
+    // Each range writes through its own handle, so the seeks don't interfere
+    let mut file: tokio::fs::File = tokio::fs::OpenOptions::new().write(true).open(&path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+
+    let mut byte_stream = response.bytes_stream();
+    let mut written: u64 = 0;
+
+    loop {
+        // Fail if the next chunk stalls, as for a single connection
+        let chunk = match stall_timeout {
+            Some(stall_timeout) => tokio::time::timeout(stall_timeout, byte_stream.next())
+                .await
+                .map_err(|_| DownloadError::StallError(stall_timeout))?,
+            None => byte_stream.next().await,
+        };
 
-        // Send the record over a channel to be processed
-        let record_info = RecordInfo {
-            record,
-            position: pos.byte(),
+        let Some(chunk) = chunk else {
+            break;
         };
 
-        // Send the record over the channel
-        tx_channel.send(record_info)?;
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        downloaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
     }
 
-    // Return Ok
-    Ok(())
+    file.flush().await?;
+
+    // A short or long range would leave the file corrupt
+    match written == end - start + 1 {
+        true => Ok(()),
+        false => Err(DownloadError::RangeError(format!(
+            "expected {} bytes for bytes {}-{}, got {}",
+            end - start + 1,
+            start,
+            end,
+            written
+        ))),
+    }
+}
+
+// Show the bytes written by the ranges until the counter is set to u64::MAX
+async fn show_range_progress(progress_mode: ProgressMode, content_length: u64, downloaded: Arc<AtomicU64>) {
+    let mut progress: Progress = Progress::new(progress_mode, content_length, "Downloading ranges ", ProgressUnits::Bytes);
+
+    loop {
+        let position: u64 = downloaded.load(Ordering::Relaxed);
+
+        if position == u64::MAX {
+            break;
+        }
+
+        progress.set_position(position);
+        tokio::time::sleep(UPDATE_INTERVAL).await;
+    }
+
+    progress.finish();
+}
+
+// Reads the reassembled download, deleting the temporary file when the reader is dropped
+struct TempFileReader {
+    file: tokio::fs::File,
+    #[allow(dead_code)]
+    temp_file: NamedTempFile,
+}
+
+impl AsyncRead for TempFileReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
+    }
+}
+
+// Wrap a byte stream so that it fails if the next chunk takes longer than stall_timeout to arrive
+//
+// Each poll gets a fresh timer, so a slow but steady download never trips it
+fn with_stall_timeout<S, B, D>(
+    byte_stream: S,
+    stall_timeout: Duration,
+) -> impl Stream<Item = Result<B, DownloadError<D>>> + Send + Unpin
+where
+    S: Stream<Item = Result<B, DownloadError<D>>> + Send + Unpin + 'static,
+    B: Send + 'static,
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    Box::pin(stream::unfold(Some(byte_stream), move |byte_stream| async move {
+        // Stop after a stall has been reported
+        let mut byte_stream: S = byte_stream?;
+
+        match tokio::time::timeout(stall_timeout, byte_stream.next()).await {
+            Ok(Some(chunk)) => Some((chunk, Some(byte_stream))),
+            Ok(None) => None,
+            Err(_) => Some((Err(DownloadError::StallError(stall_timeout)), None)),
+        }
+    }))
+}
+
+// A download being resumed after interruptions, from the first byte not yet received
+struct Reconnect {
+    http_client: Client,
+    url: String,
+    // The ETag or Last-Modified of the first response, so a changed file isn't spliced onto the old one
+    validator: Option<String>,
+    received: u64,
+    content_length: u64,
+    reconnects: u32,
+    max_reconnects: u32,
+}
+
+// Continue an interrupted download with a range request for the rest of the file
+//
+// The reconnect happens below the CSV reader, which sees one continuous stream of bytes. So the record
+// positions keep increasing across a reconnect, and a record cut by the interruption is read once, from
+// the bytes either side of it, rather than being lost or sent twice
+fn with_reconnects<D>(
+    http_client: Client,
+    url: &str,
+    response: Response,
+    content_length: u64,
+    max_reconnects: u32,
+) -> impl Stream<Item = Result<Bytes, DownloadError<D>>> + Send + Unpin
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    let reconnect = Reconnect {
+        http_client,
+        url: url.to_string(),
+        validator: header_value(response.headers(), ETAG).or_else(|| header_value(response.headers(), LAST_MODIFIED)),
+        received: 0,
+        content_length,
+        reconnects: 0,
+        max_reconnects,
+    };
+
+    let byte_stream = response.bytes_stream().boxed();
+
+    Box::pin(stream::unfold(Some((reconnect, byte_stream)), |state| async move {
+        // Stop after an error has been reported
+        let (mut reconnect, mut byte_stream) = state?;
+
+        loop {
+            let error: DownloadError<D> = match byte_stream.next().await {
+                Some(Ok(chunk)) => {
+                    reconnect.received += chunk.len() as u64;
+                    return Some((Ok(chunk), Some((reconnect, byte_stream))));
+                }
+                // The end of a stream of unknown length, when received is always at least the zero length
+                None if reconnect.received >= reconnect.content_length => return None,
+                Some(Err(error)) => error.into(),
+                None => DownloadError::RangeError(format!(
+                    "the connection closed after {} of {} bytes",
+                    reconnect.received, reconnect.content_length
+                )),
+            };
+
+            if reconnect.reconnects >= reconnect.max_reconnects {
+                return Some((Err(error), None));
+            }
+
+            reconnect.reconnects += 1;
+
+            let text = format!(
+                "Warning: the download was interrupted at byte {} ({}), reconnecting ({} of {})",
+                reconnect.received, error, reconnect.reconnects, reconnect.max_reconnects
+            );
+            report::warning(&text);
+
+            match resume_download(&reconnect).await {
+                Ok(response) => byte_stream = response.bytes_stream().boxed(),
+                Err(error) => return Some((Err(error), None)),
+            }
+        }
+    }))
+}
+
+// Send a GET request, retrying connection errors, timeouts and 5xx statuses with exponential backoff
+//
+// Any other failure, such as a 4xx status, is returned straight away, as is the last error once
+// every attempt has been used
+async fn send_with_retries<D>(http_client: &Client, url: &str, max_attempts: u32, base_delay: Duration) -> Result<Response, DownloadError<D>>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    let mut delay: Duration = base_delay;
+    let mut attempt: u32 = 1;
+
+    loop {
+        let error: reqwest::Error = match http_client.get(url).send().await.and_then(Response::error_for_status) {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+
+        if attempt >= max_attempts || !is_transient(&error) {
+            return Err(error.into());
+        }
+
+        let text = format!(
+            "Attempt {} of {} failed ({}), retrying in {:.1}s",
+            attempt,
+            max_attempts,
+            error,
+            delay.as_secs_f64()
+        );
+        info!(notice = true, "{}", text);
+
+        tokio::time::sleep(delay).await;
+
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+// Whether a failed request might succeed if sent again
+fn is_transient(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error(),
+        // A reset connection is reported as a request error
+        None => error.is_connect() || error.is_timeout() || error.is_request(),
+    }
+}
+
+// Request the bytes after those already received, checking the server sent exactly that range
+async fn resume_download<D>(reconnect: &Reconnect) -> Result<Response, DownloadError<D>>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    debug!(received = reconnect.received, "Resuming the download");
+
+    // Ask for the rest of a file of unknown length with an open ended range
+    let range: String = match reconnect.content_length {
+        0 => format!("bytes={}-", reconnect.received),
+        content_length => format!("bytes={}-{}", reconnect.received, content_length - 1),
+    };
+
+    let mut request = reconnect.http_client.get(&reconnect.url).header(RANGE, range);
+
+    // The server sends the whole file instead of the range if it has changed since the first response
+    if let Some(validator) = &reconnect.validator {
+        request = request.header(IF_RANGE, validator);
+    }
+
+    let response: Response = request.send().await?.error_for_status()?;
+
+    let expected: String = format!("bytes {}-", reconnect.received);
+
+    match (response.status(), header_value(response.headers(), CONTENT_RANGE)) {
+        (StatusCode::PARTIAL_CONTENT, Some(range)) if range.starts_with(&expected) => Ok(response),
+        (StatusCode::PARTIAL_CONTENT, range) => Err(DownloadError::RangeError(format!(
+            "asked to resume from byte {}, got the range {}",
+            reconnect.received,
+            range.as_deref().unwrap_or("unknown")
+        ))),
+        (status, _) => Err(DownloadError::RangeError(format!(
+            "could not resume from byte {}, the server answered {} so the file may have changed",
+            reconnect.received, status
+        ))),
+    }
+}
+
+// The local path of a file:// URL, None for any other scheme
+pub fn file_url_path(url: &str) -> Option<PathBuf> {
+    Url::parse(url).ok().filter(|url| url.scheme() == "file")?.to_file_path().ok()
+}
+
+
+fn header_value(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+#[cfg(feature = "s3")]
+fn s3_error<D, E>(
+    url: &str,
+    error: aws_sdk_s3::error::SdkError<E, aws_sdk_s3::config::http::HttpResponse>,
+) -> DownloadError<D>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+    E: std::error::Error + 'static,
+{
+    use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+
+    // Make the common failures readable, the full error context is very long
+    let text: String = match &error {
+        SdkError::ServiceError(service_error) => match service_error.raw().status().as_u16() {
+            403 => format!("access denied to {}, check the credentials and bucket policy", url),
+            404 => format!("{} does not exist", url),
+            _ => format!("{}: {}", url, DisplayErrorContext(&error)),
+        },
+        SdkError::DispatchFailure(_) | SdkError::ConstructionFailure(_) => format!(
+            "could not send the request for {}, check the AWS credentials and region: {}",
+            url,
+            DisplayErrorContext(&error)
+        ),
+        _ => format!("{}: {}", url, DisplayErrorContext(&error)),
+    };
+
+    DownloadError::S3Error(text)
+}
+
+// What to do with a record that fails to parse
+struct ErrorPolicy {
+    // Skip failed records, until this many have been skipped if there is a limit
+    on_error: OnError,
+    // A limit also skips the failed records, even with OnError::Abort
+    max_errors: Option<u64>,
+    record_errors: Arc<AtomicU64>,
+    // The dialect to read a failed record again with, with its quotes as ordinary characters
+    quote_fallback: Option<CsvDialect>,
+    recovered_records: Arc<AtomicU64>,
+}
+
+async fn iterate_records<R, D>(
+    csv_reader: &mut AsyncDeserializer<R>,
+    tx_channel: mpsc::Sender<RecordInfo<D>>,
+    mut raw_lines: Option<RawLines>,
+    keep_raw: bool,
+    error_policy: &ErrorPolicy,
+    mut pause: Option<Pause>,
+    memory_budget: Option<Arc<MemoryBudget>>,
+) -> Result<(), DownloadError<D>>
+where
+    R: AsyncRead + Send + Unpin,
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    // The headers, after any renames, name the fields of each record
+    let headers: StringRecord = csv_reader.headers().await?.clone();
+    let mut string_record: StringRecord = StringRecord::new();
+
+    // Only the first record with extra trailing fields is reported
+    let mut extra_fields_reported: bool = false;
+
+    // Iterate over the records, noting the position before each one is read
+    loop {
+        // Stop pulling from the source while the download is paused
+        if let Some(pause) = pause.as_mut() {
+            pause.wait_while_paused().await;
+        }
+
+        let position: u64 = csv_reader.position().byte();
+
+        let result: Result<D, csv_async::Error> = match csv_reader.read_record(&mut string_record).await {
+            Ok(true) => {
+                if string_record.len() > headers.len() && !extra_fields_reported {
+                    let text = format!(
+                        "Warning: the record at byte {} has {} fields but the header has {}, extra trailing fields are dropped",
+                        position,
+                        string_record.len(),
+                        headers.len()
+                    );
+                    report::warning(&text);
+
+                    extra_fields_reported = true;
+                }
+
+                string_record.deserialize(Some(&headers))
+            }
+            Ok(false) => break,
+            Err(error) => Err(error),
+        };
+
+        // The source text of the record, which also frees the bytes recorded before it
+        let raw: Option<String> = raw_lines
+            .as_mut()
+            .map(|raw_lines| raw_lines.take(position, csv_reader.position().byte()));
+
+        let record: D = match result {
+            Ok(record) => record,
+            Err(error) if error.is_io_error() => return Err(error.into()),
+            Err(error) => {
+                // Try the record again without quoting, it may hold several records if an unpaired quote ran on past a line
+                let recovered: Option<Vec<(D, String)>> = match (&error_policy.quote_fallback, &raw) {
+                    (Some(csv_dialect), Some(raw)) => parse_relaxed(raw, &headers, csv_dialect).await,
+                    _ => None,
+                };
+
+                if let Some(recovered) = recovered {
+                    error_policy.recovered_records.fetch_add(recovered.len() as u64, Ordering::Relaxed);
+
+                    let text = format!(
+                        "Warning: read {} record(s) at byte {} without quoting, after: {}",
+                        recovered.len(),
+                        position,
+                        error
+                    );
+                    report::warning(&text);
+
+                    for (record, line) in recovered {
+                        let field_bytes: usize = line.len();
+                        let raw: Option<String> = keep_raw.then_some(line);
+                        send_record(&tx_channel, record, position, raw, field_bytes, memory_budget.as_ref()).await?;
+                    }
+
+                    continue;
+                }
+
+                // Skip malformed records if asked to, until there are too many if a limit is set, I/O errors are always fatal
+                if error_policy.on_error == OnError::Abort && error_policy.max_errors.is_none() {
+                    return Err(error.into());
+                }
+
+                let errors: u64 = error_policy.record_errors.fetch_add(1, Ordering::Relaxed) + 1;
+
+                let text = format!("Warning: skipping the record at byte {}: {}", position, error);
+                report::warning(&text);
+
+                if error_policy.max_errors.is_some_and(|max_errors| errors >= max_errors) {
+                    return Err(DownloadError::TooManyErrors(errors));
+                }
+
+                continue;
+            }
+        };
+
+        // Only send the source line along if it was asked for
+        let raw: Option<String> = raw.filter(|_| keep_raw);
+
+        send_record(&tx_channel, record, position, raw, string_record.as_slice().len(), memory_budget.as_ref()).await?;
+    }
+
+    // Return Ok
+    Ok(())
+}
+
+// Send a record over the channel to be processed, first waiting for its share of the memory budget
+async fn send_record<D>(
+    tx_channel: &mpsc::Sender<RecordInfo<D>>,
+    record: D,
+    position: u64,
+    raw: Option<String>,
+    field_bytes: usize,
+    memory_budget: Option<&Arc<MemoryBudget>>,
+) -> Result<(), DownloadError<D>>
+where
+    D: DeserializeOwned + Send + Sync + 'static,
+{
+    // Estimate the record as its fields and source line on top of its fixed size
+    let memory: Option<MemoryPermit> = match memory_budget {
+        Some(memory_budget) => {
+            let bytes: usize = size_of::<RecordInfo<D>>() + field_bytes + raw.as_ref().map_or(0, String::len);
+            Some(memory_budget.acquire(bytes).await.map_err(|_| DownloadError::ChannelError)?)
+        }
+        None => None,
+    };
+
+    let record_info = RecordInfo {
+        record,
+        position,
+        raw,
+        memory,
+    };
+
+    tx_channel.send(record_info).await?;
+
+    Ok(())
+}
+
+// Read the source text of a failed record with its quotes as ordinary characters, returning each
+// record it holds with its line, or None if any of them still doesn't fit the header
+//
+// Every line must have exactly as many fields as the header, a quoted delimiter read as a field
+// boundary would otherwise shift the values into the wrong fields
+async fn parse_relaxed<D>(raw: &str, headers: &StringRecord, csv_dialect: &CsvDialect) -> Option<Vec<(D, String)>>
+where
+    D: DeserializeOwned,
+{
+    let mut reader = csv_dialect
+        .relaxed()
+        .builder()
+        .has_headers(false)
+        .create_reader(Cursor::new(raw.as_bytes().to_vec()));
+
+    let mut records: Vec<(D, String)> = Vec::new();
+    let mut string_record: StringRecord = StringRecord::new();
+
+    loop {
+        let start: usize = reader.position().byte() as usize;
+
+        if !reader.read_record(&mut string_record).await.ok()? {
+            break;
+        }
+
+        if string_record.len() != headers.len() {
+            return None;
+        }
+
+        let unquoted: StringRecord = string_record.iter().map(|field| csv_dialect.unquote(field)).collect();
+
+        // The source line of this record, without the line break
+        let end: usize = (reader.position().byte() as usize).min(raw.len());
+        let line: String = raw[start.min(end)..end]
+            .trim_matches(|character: char| match csv_dialect.terminator {
+                Terminator::Any(terminator) => character == terminator as char,
+                _ => character == '\r' || character == '\n',
+            })
+            .to_string();
+
+        records.push((unquoted.deserialize(Some(headers)).ok()?, line));
+    }
+
+    (!records.is_empty()).then_some(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Row {
+        icao24: String,
+        model: String,
+    }
+
+    const FIXTURE: &str = "'icao24','model'\r\n'4ca1fa','A320-214'\r\n'a0b1c2','B738'\r\n";
+
+    async fn read_all(download_info: &mut DownloadInfo<Row>, csv: &str) -> Result<Vec<RecordInfo<Row>>, DownloadError<Row>> {
+        let join_handle = download_info
+            .download_from_reader(Cursor::new(csv.as_bytes().to_vec()))
+            .await?;
+
+        // Read the records as they arrive, the reader waits while the channel is full
+        let mut records: Vec<RecordInfo<Row>> = Vec::new();
+
+        while let Some(record_info) = download_info.rx_channel.recv().await {
+            records.push(record_info);
+        }
+
+        join_handle.await.expect("the record task panicked")?;
+
+        Ok(records)
+    }
+
+    #[tokio::test]
+    async fn reads_records_from_a_reader() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        let records = read_all(&mut download_info, FIXTURE).await.unwrap();
+
+        let rows: Vec<&Row> = records.iter().map(|record_info| &record_info.record).collect();
+        assert_eq!(
+            rows,
+            [
+                &Row { icao24: "4ca1fa".to_string(), model: "A320-214".to_string() },
+                &Row { icao24: "a0b1c2".to_string(), model: "B738".to_string() },
+            ]
+        );
+
+        // The positions increase through the input, they drive the progress bar
+        assert!(records[0].position > 0);
+        assert!(records[1].position > records[0].position);
+        assert!(records[0].raw.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_full_channel_pauses_the_reader() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::with_capacity(2);
+
+        let csv: String = numbered_records(100);
+        let join_handle = download_info.download_from_reader(Cursor::new(csv.into_bytes())).await.unwrap();
+
+        // Nothing is read, so the reader stops once the channel holds two records
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!join_handle.is_finished());
+
+        let mut records: usize = 0;
+
+        while download_info.rx_channel.recv().await.is_some() {
+            records += 1;
+        }
+
+        join_handle.await.unwrap().unwrap();
+        assert_eq!(records, 100);
+    }
+
+    #[tokio::test]
+    async fn counts_every_byte_read() {
+        for csv in [FIXTURE, "'icao24','model'\r\n'4ca1fa','A320-214'", "'icao24','model'\n'4ca1fa','A320-214'\n\n"] {
+            let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+            read_all(&mut download_info, csv).await.unwrap();
+
+            assert_eq!(download_info.bytes_read(), csv.len() as u64, "{:?}", csv);
+        }
+    }
+
+    #[tokio::test]
+    async fn renamed_columns_fill_the_fields() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_column_renames(HashMap::from([("hex".to_string(), "icao24".to_string())]));
+
+        let records = read_all(&mut download_info, "'hex','model'\r\n'4ca1fa','A320-214'\r\n").await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].record.icao24, "4ca1fa");
+    }
+
+    #[tokio::test]
+    async fn missing_columns_are_reported() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+
+        match read_all(&mut download_info, "'hex','model'\r\n'4ca1fa','A320-214'\r\n").await {
+            Err(DownloadError::HeaderError(text)) => assert!(text.contains("icao24"), "{}", text),
+            Err(error) => panic!("expected a header error, got {}", error),
+            Ok(_) => panic!("expected a header error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_source_lines() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_keep_raw(true);
+
+        // Unquoted fields, padding and doubled quotes are kept as they are in the source
+        let csv: &str = "'icao24','model'\r\n4ca1fa, 'A320-214'\r\n'a0b1c2','O''Hare'\n'last','line'";
+        let records = read_all(&mut download_info, csv).await.unwrap();
+
+        let raw: Vec<&str> = records.iter().map(|record_info| record_info.raw.as_deref().unwrap()).collect();
+        assert_eq!(raw, ["4ca1fa, 'A320-214'", "'a0b1c2','O''Hare'", "'last','line'"]);
+        assert_eq!(records[1].record.model, "O'Hare");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_source_lines_with_a_custom_terminator() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_keep_raw(true);
+        download_info.set_csv_dialect(CsvDialect {
+            terminator: csv_async::Terminator::Any(b';'),
+            ..CsvDialect::default()
+        });
+
+        let records = read_all(&mut download_info, "'icao24','model';'4ca1fa','A320\r\n214';'a0b1c2','B738';").await.unwrap();
+
+        let raw: Vec<&str> = records.iter().map(|record_info| record_info.raw.as_deref().unwrap()).collect();
+        assert_eq!(raw, ["'4ca1fa','A320\r\n214'", "'a0b1c2','B738'"]);
+    }
+
+    #[tokio::test]
+    async fn extra_trailing_fields_are_dropped() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_keep_raw(true);
+
+        // Someone appended a column to the records without updating the header
+        let csv: &str = "'icao24','model'\r\n'4ca1fa','A320-214',''\r\n'a0b1c2','B738','x','y'\r\n'c0ffee','E190'\r\n";
+        let records = read_all(&mut download_info, csv).await.unwrap();
+
+        let rows: Vec<&Row> = records.iter().map(|record_info| &record_info.record).collect();
+        assert_eq!(
+            rows,
+            [
+                &Row { icao24: "4ca1fa".to_string(), model: "A320-214".to_string() },
+                &Row { icao24: "a0b1c2".to_string(), model: "B738".to_string() },
+                &Row { icao24: "c0ffee".to_string(), model: "E190".to_string() },
+            ]
+        );
+
+        // The extras are only kept in the source line
+        assert_eq!(records[1].raw.as_deref(), Some("'a0b1c2','B738','x','y'"));
+    }
+
+    #[tokio::test]
+    async fn missing_trailing_fields_are_still_errors() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+
+        assert!(read_all(&mut download_info, "'icao24','model'\r\n'4ca1fa'\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn records_with_unpaired_quotes_are_read_again_without_quoting() {
+        // The missing quote after 4ca1fa takes the delimiter into the first field, leaving one field
+        let csv: &str = "'icao24','model'\r\n'4ca1fa,'A320'\r\n'a0b1c2','B738'\r\n'last','E190'\r\n";
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        assert!(read_all(&mut download_info, csv).await.is_err());
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_quote_fallback(true);
+        download_info.set_keep_raw(true);
+
+        let records: Vec<RecordInfo<Row>> = read_all(&mut download_info, csv).await.unwrap();
+        let rows: Vec<(&str, &str)> = records.iter().map(|record_info| (record_info.record.icao24.as_str(), record_info.record.model.as_str())).collect();
+
+        assert_eq!(rows, [("4ca1fa", "A320"), ("a0b1c2", "B738"), ("last", "E190")]);
+        assert_eq!(records[1].raw.as_deref(), Some("'a0b1c2','B738'"));
+        assert_eq!(download_info.recovered_records(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_quote_fallback_still_rejects_records_with_the_wrong_fields() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_quote_fallback(true);
+        download_info.set_max_errors(Some(10));
+
+        let records = read_all(&mut download_info, "'icao24','model'\r\n'4ca1fa'\r\n'a0b1c2','B738'\r\n").await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(download_info.recovered_records(), 0);
+        assert_eq!(download_info.record_errors(), 1);
+    }
+
+    #[tokio::test]
+    async fn skipped_records_dont_stop_the_download() {
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_on_error(OnError::Skip);
+
+        // Without a limit any number of bad records are skipped
+        let mut csv: String = "'icao24','model'\r\n".to_string();
+
+        for index in 0..50 {
+            csv.push_str(&format!("'{:06x}'\r\n'{:06x}','A320'\r\n", index, index));
+        }
+
+        let records = read_all(&mut download_info, &csv).await.unwrap();
+
+        assert_eq!(records.len(), 50);
+        assert_eq!(download_info.record_errors(), 50);
+    }
+
+    // Serve body over HTTP on a local port, answering HEAD and GET with optional byte ranges,
+    // returning the URL and a count of the range requests. With cut_at, the first full GET
+    // drops the connection after that many bytes of the body
+    async fn serve(body: Vec<u8>, cut_at: Option<usize>) -> (String, Arc<AtomicU64>) {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let body: Arc<Vec<u8>> = Arc::new(body);
+        let range_requests: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+        let counter: Arc<AtomicU64> = range_requests.clone();
+        let cut_at: Arc<std::sync::Mutex<Option<usize>>> = Arc::new(std::sync::Mutex::new(cut_at));
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let body: Arc<Vec<u8>> = body.clone();
+                let counter: Arc<AtomicU64> = counter.clone();
+                let cut_at: Arc<std::sync::Mutex<Option<usize>>> = cut_at.clone();
+
+                tokio::spawn(async move {
+                    // Read the request head, the requests have no body
+                    let mut request: Vec<u8> = Vec::new();
+                    let mut buffer = [0u8; 1024];
+
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+
+                    let request: String = String::from_utf8_lossy(&request).to_lowercase();
+                    let range: Option<(usize, usize)> = request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .and_then(|range| range.trim().split_once('-'))
+                        .map(|(start, end)| (start.parse().unwrap(), end.parse().unwrap()));
+
+                    let (status, content): (&str, &[u8]) = match range {
+                        Some((start, end)) => {
+                            counter.fetch_add(1, Ordering::Relaxed);
+                            ("206 Partial Content", &body[start..=end])
+                        }
+                        None => ("200 OK", &body[..]),
+                    };
+
+                    let content_range: String = match range {
+                        Some((start, end)) => format!("Content-Range: bytes {}-{}/{}\r\n", start, end, body.len()),
+                        None => String::new(),
+                    };
+
+                    let mut response: Vec<u8> = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Accept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+                        status,
+                        content.len(),
+                        content_range
+                    )
+                    .into_bytes();
+
+                    // Only the first full GET is cut short
+                    let cut_at: Option<usize> = match (range, request.starts_with("get")) {
+                        (None, true) => cut_at.lock().unwrap().take(),
+                        _ => None,
+                    };
+
+                    if !request.starts_with("head") {
+                        response.extend_from_slice(&content[..cut_at.unwrap_or(content.len())]);
+                    }
+
+                    let _ = socket.write_all(&response).await;
+                });
+            }
+        });
+
+        (format!("http://{}/aircraft.csv", address), range_requests)
+    }
+
+    // A hash of the records in the order they were received
+    async fn download_checksum(url: &str, download_connections: usize) -> (usize, u64) {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_keep_raw(true);
+        download_info.set_download_connections(download_connections);
+
+        let join_handle = download_info.download(url).await.unwrap();
+
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        let mut records: usize = 0;
+
+        while let Some(record_info) = download_info.rx_channel.recv().await {
+            record_info.raw.hash(&mut hasher);
+            record_info.record.icao24.hash(&mut hasher);
+            record_info.record.model.hash(&mut hasher);
+            records += 1;
+        }
+
+        join_handle.await.unwrap().unwrap();
+
+        (records, hasher.finish())
+    }
+
+    #[tokio::test]
+    async fn parallel_ranges_match_a_single_connection() {
+        // Enough records that the range boundaries fall inside lines
+        let mut csv: String = "'icao24','model'\r\n".to_string();
+
+        for index in 0..5000 {
+            csv.push_str(&format!("'{:06x}','Model {}'\r\n", index, index));
+        }
+
+        let (url, range_requests) = serve(csv.into_bytes(), None).await;
+
+        let single = download_checksum(&url, 1).await;
+        assert_eq!(range_requests.load(Ordering::Relaxed), 0);
+
+        let parallel = download_checksum(&url, 7).await;
+        assert_eq!(range_requests.load(Ordering::Relaxed), 7);
+
+        assert_eq!(single.0, 5000);
+        assert_eq!(parallel, single);
+    }
+
+    fn numbered_records(count: usize) -> String {
+        let mut csv: String = "'icao24','model'\r\n".to_string();
+
+        for index in 0..count {
+            csv.push_str(&format!("'{:06x}','Model {}'\r\n", index, index));
+        }
+
+        csv
+    }
+
+    #[tokio::test]
+    async fn reconnects_without_losing_or_repeating_records() {
+        let csv: String = numbered_records(2000);
+
+        // Drop the connection part way through a record
+        let cut_at: usize = csv.len() / 2 + 7;
+        assert!(!csv[..cut_at].ends_with("\r\n"));
+
+        let (url, range_requests) = serve(csv.into_bytes(), Some(cut_at)).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_max_reconnects(1);
+
+        let join_handle = download_info.download(&url).await.unwrap();
+
+        let mut icao24s: HashSet<String> = HashSet::new();
+        let mut last_position: Option<u64> = None;
+
+        while let Some(record_info) = download_info.rx_channel.recv().await {
+            // No record is sent twice, and the positions keep increasing across the reconnect
+            assert!(icao24s.insert(record_info.record.icao24), "a record was repeated");
+            assert!(last_position.is_none_or(|last_position| record_info.position > last_position));
+            last_position = Some(record_info.position);
+        }
+
+        join_handle.await.unwrap().unwrap();
+        assert_eq!(range_requests.load(Ordering::Relaxed), 1);
+
+        assert_eq!(icao24s.len(), 2000);
+    }
+
+    // A server that answers the first requests with this status before serving the body
+    async fn serve_after_failures(body: Vec<u8>, status: &'static str, failures: u64) -> (String, Arc<AtomicU64>) {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+        let counter: Arc<AtomicU64> = requests.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buffer = [0u8; 1024];
+                let _ = socket.read(&mut buffer).await;
+
+                let response: Vec<u8> = match counter.fetch_add(1, Ordering::Relaxed) < failures {
+                    true => format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).into_bytes(),
+                    false => {
+                        let mut response: Vec<u8> =
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+                        response.extend_from_slice(&body);
+                        response
+                    }
+                };
+
+                let _ = socket.write_all(&response).await;
+            }
+        });
+
+        (format!("http://{}/aircraft.csv", address), requests)
+    }
+
+    // A server that answers every request with this response, then holds the connection until the client closes it
+    async fn serve_response(response: Vec<u8>) -> String {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buffer = [0u8; 1024];
+                let _ = socket.read(&mut buffer).await;
+                let _ = socket.write_all(&response).await;
+
+                tokio::spawn(async move {
+                    let _ = socket.read(&mut buffer).await;
+                });
+            }
+        });
+
+        format!("http://{}/aircraft.csv", address)
+    }
+
+    #[tokio::test]
+    async fn a_server_that_stops_sending_times_out() {
+        let body: String = numbered_records(10);
+        let mut response: Vec<u8> = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len() * 2).into_bytes();
+        response.extend_from_slice(body.as_bytes());
+
+        let url: String = serve_response(response).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_http_timeout(Duration::from_millis(200));
+
+        let join_handle = download_info.download(&url).await.unwrap();
+
+        while download_info.rx_channel.recv().await.is_some() {}
+
+        let error = tokio::time::timeout(Duration::from_secs(5), join_handle).await.unwrap().unwrap().unwrap_err();
+        let text: String = crate::report::with_causes(&error);
+        assert!(text.contains("Timed out waiting for the server"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn chunked_responses_are_read_with_an_unknown_length() {
+        let body: String = numbered_records(10);
+        let mut response: Vec<u8> = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+        response.extend_from_slice(format!("{:x}\r\n{}\r\n0\r\n\r\n", body.len(), body).as_bytes());
+
+        let url: String = serve_response(response).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        let join_handle = download_info.download(&url).await.unwrap();
+        assert_eq!(download_info.content_length, 0);
+
+        let mut records: usize = 0;
+
+        while download_info.rx_channel.recv().await.is_some() {
+            records += 1;
+        }
+
+        join_handle.await.unwrap().unwrap();
+        assert_eq!(records, 10);
+        assert_eq!(download_info.bytes_read(), body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn an_explicit_zero_length_is_an_error() {
+        let url: String = serve_response(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        assert!(matches!(download_info.download(&url).await, Err(DownloadError::ZeroLengthError)));
+    }
+
+    #[test]
+    fn errors_keep_their_cause() {
+        use std::error::Error;
+
+        let error: DownloadError<Row> = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed").into();
+        let source = error.source().expect("the I/O error is the source");
+
+        assert_eq!(source.to_string(), "connection closed");
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+        assert!(DownloadError::<Row>::TooManyErrors(3).source().is_none());
+
+        // The cause is left out of the message, so it is only printed once when the chain is followed
+        assert_eq!(error.to_string(), "I/O error");
+        assert_eq!(crate::report::with_causes(&error), "I/O error: connection closed");
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (url, requests) = serve_after_failures(numbered_records(10).into_bytes(), "503 Service Unavailable", 2).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_max_attempts(3);
+        download_info.retry_delay = Duration::from_millis(1);
+
+        let join_handle = download_info.download(&url).await.unwrap();
+
+        let mut records: usize = 0;
+
+        while download_info.rx_channel.recv().await.is_some() {
+            records += 1;
+        }
+
+        join_handle.await.unwrap().unwrap();
+        assert_eq!(records, 10);
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn the_last_error_is_returned_once_the_attempts_run_out() {
+        let (url, requests) = serve_after_failures(numbered_records(10).into_bytes(), "502 Bad Gateway", 5).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_max_attempts(2);
+        download_info.retry_delay = Duration::from_millis(1);
+
+        let result = download_info.download(&url).await;
+        assert!(matches!(result, Err(DownloadError::ReqwestError(error)) if error.status() == Some(StatusCode::BAD_GATEWAY)));
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (url, requests) = serve_after_failures(numbered_records(10).into_bytes(), "404 Not Found", 1).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+        download_info.set_max_attempts(3);
+        download_info.retry_delay = Duration::from_millis(1);
+
+        assert!(download_info.download(&url).await.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn local_files_are_read_by_path_and_file_url() {
+        let csv: String = numbered_records(100);
+        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
+        std::fs::write(temp_file.path(), &csv).unwrap();
+
+        let file_url: String = Url::from_file_path(temp_file.path()).unwrap().to_string();
+        assert_eq!(file_url_path(&file_url).as_deref(), Some(temp_file.path()));
+        assert_eq!(file_url_path("https://opensky-network.org/aircraft.csv"), None);
+
+        for url in [None, Some(file_url)] {
+            let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+
+            let join_handle = match &url {
+                Some(url) => download_info.download(url).await.unwrap(),
+                None => download_info.download_file(temp_file.path()).await.unwrap(),
+            };
+
+            let mut records: usize = 0;
+
+            while download_info.rx_channel.recv().await.is_some() {
+                records += 1;
+            }
+
+            join_handle.await.unwrap().unwrap();
+            assert_eq!(records, 100);
+            assert_eq!(download_info.content_length, csv.len() as u64);
+            assert_eq!(download_info.bytes_read(), csv.len() as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_fail_without_reconnects() {
+        let csv: String = numbered_records(2000);
+        let (url, _) = serve(csv.into_bytes(), Some(1000)).await;
+
+        let mut download_info: DownloadInfo<Row> = DownloadInfo::new();
+
+        let join_handle = download_info.download(&url).await.unwrap();
+        assert!(join_handle.await.unwrap().is_err());
+    }
 }
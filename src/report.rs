@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use colored::Colorize;
+
+use crate::metrics::metrics;
+
+// Whether errors and warnings are also written as GitHub Actions workflow commands
+static GITHUB_ANNOTATIONS: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_github_annotations() {
+    GITHUB_ANNOTATIONS.store(true, Ordering::Relaxed);
+}
+
+// Log a fatal error, and print an ::error:: annotation if enabled
+//
+// Before the logging is set up, e.g. for a bad .env file, the error is printed directly
+pub fn error(text: &str) {
+    match tracing::dispatcher::has_been_set() {
+        true => tracing::error!("{}", text),
+        false => eprintln!("{}", text.red().bold()),
+    }
+    annotate("error", text);
+    metrics().error();
+}
+
+// Log a data quality warning, and print a ::warning:: annotation if enabled
+pub fn warning(text: &str) {
+    match tracing::dispatcher::has_been_set() {
+        true => tracing::warn!("{}", text),
+        false => eprintln!("{}", text.yellow().bold()),
+    }
+    annotate("warning", text);
+    metrics().warning();
+}
+
+// An error followed by each of its causes, which the download and database errors leave out of their own message
+pub fn with_causes(error: &dyn std::error::Error) -> String {
+    let mut text: String = error.to_string();
+    let mut source: Option<&dyn std::error::Error> = error.source();
+
+    while let Some(cause) = source {
+        text.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+
+    text
+}
+
+fn annotate(level: &str, text: &str) {
+    if GITHUB_ANNOTATIONS.load(Ordering::Relaxed) {
+        // Workflow commands end at a newline, so escape the message as GitHub expects
+        let message: String = text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+        println!("::{}::{}", level, message);
+    }
+}
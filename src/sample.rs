@@ -0,0 +1,83 @@
+use bson::Document;
+
+use rand::Rng;
+
+use serde::Serialize;
+
+// A uniform random sample of the records sent to the database, kept using reservoir sampling
+// so that only `capacity` documents are ever held in memory
+pub struct RecordSample {
+    capacity: usize,
+    seen: u64,
+    documents: Vec<Document>,
+}
+
+// The result of reading a sampled record back from the database
+pub enum SampleCheck {
+    Match,
+    Missing,
+    Mismatch,
+}
+
+impl RecordSample {
+    pub fn new(capacity: usize) -> Self {
+        RecordSample {
+            capacity,
+            seen: 0,
+            documents: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn offer<T>(&mut self, record: &T)
+    where
+        T: Serialize,
+    {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.seen += 1;
+
+        // Fill the reservoir, then replace entries with decreasing probability
+        let slot: Option<usize> = match self.documents.len() < self.capacity {
+            true => Some(self.documents.len()),
+            false => {
+                let index: u64 = rand::rng().random_range(0..self.seen);
+                (index < self.capacity as u64).then_some(index as usize)
+            }
+        };
+
+        // Only serialize the records that are kept
+        if let Some(slot) = slot {
+            if let Ok(document) = bson::to_document(record) {
+                match slot < self.documents.len() {
+                    true => self.documents[slot] = document,
+                    false => self.documents.push(document),
+                }
+            }
+        }
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+}
+
+// Compare a sent document against the documents stored under the same key
+pub fn check_sample(sent: &Document, stored: &[Document]) -> SampleCheck {
+    if stored.is_empty() {
+        return SampleCheck::Missing;
+    }
+
+    // The key may not be unique, so any stored document matching is a pass
+    let matched: bool = stored.iter().any(|document| {
+        let mut document: Document = document.clone();
+        document.remove("_id");
+        document == *sent
+    });
+
+    match matched {
+        true => SampleCheck::Match,
+        false => SampleCheck::Mismatch,
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bson::spec::BinarySubtype;
+use bson::{Binary, Bson, Document};
+
+use clap::ValueEnum;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use serde::ser::Error;
+use serde::{Serialize, Serializer};
+
+use crate::memory::MemoryPermit;
+
+// The field holding the source CSV line when --keep-raw is used
+const RAW_FIELD: &str = "_raw";
+// The field holding the start time of the run when --stamp-ingested is used
+pub const INGESTED_AT_FIELD: &str = "_ingested_at";
+
+// How empty string fields are written to the output
+//
+// Serde attributes are static, so this is applied dynamically by the OutputRecord wrapper.
+// Omitting fields gives the smallest documents on this sparse dataset, but an omitted field
+// no longer matches `{ field: "" }`, queries need to use `{ field: { $exists: false } }` instead
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NullHandling {
+    /// Keep empty fields as empty strings
+    #[default]
+    KeepEmpty,
+
+    /// Leave empty fields out of the document
+    Omit,
+
+    /// Write empty fields as null
+    Null,
+}
+
+impl NullHandling {
+    pub fn apply(&self, document: Document) -> Document {
+        match self {
+            NullHandling::KeepEmpty => document,
+            NullHandling::Omit => document
+                .into_iter()
+                .filter(|(_, value)| !is_empty_string(value))
+                .collect(),
+            NullHandling::Null => document
+                .into_iter()
+                .map(|(key, value)| match is_empty_string(&value) {
+                    true => (key, Bson::Null),
+                    false => (key, value),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn is_empty_string(value: &Bson) -> bool {
+    matches!(value, Bson::String(string) if string.is_empty())
+}
+
+// The naming convention of the stored field names
+//
+// The model's serde names are camelCase, to store other names the record is serialized through a
+// Document and its keys renamed, any explicit rename from the config file takes precedence
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum FieldNaming {
+    /// Keep the camelCase names of the OpenSky file, e.g. manufacturerName
+    #[default]
+    CamelCase,
+
+    /// Use snake_case names, e.g. manufacturer_name
+    SnakeCase,
+}
+
+impl FieldNaming {
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            FieldNaming::CamelCase => name.to_string(),
+            FieldNaming::SnakeCase => snake_case(name),
+        }
+    }
+}
+
+// Split a camelCase name before each capital, e.g. operatorIata becomes operator_iata
+fn snake_case(name: &str) -> String {
+    let mut snake_case: String = String::with_capacity(name.len() + 4);
+    let mut previous: Option<char> = None;
+
+    for character in name.chars() {
+        if character.is_ascii_uppercase() && previous.is_some_and(|previous| previous.is_ascii_lowercase() || previous.is_ascii_digit()) {
+            snake_case.push('_');
+        }
+
+        snake_case.push(character.to_ascii_lowercase());
+        previous = Some(character);
+    }
+
+    snake_case
+}
+
+// A field whose value holds several values separated by a delimiter, parsed from "field:delimiter"
+#[derive(Clone, Debug)]
+pub struct ArrayField {
+    pub field: String,
+    delimiter: String,
+}
+
+impl FromStr for ArrayField {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some((field, delimiter)) if !field.is_empty() && !delimiter.is_empty() => Ok(ArrayField {
+                field: field.to_string(),
+                delimiter: delimiter.to_string(),
+            }),
+            _ => Err(format!("expected field:delimiter, got {}", value)),
+        }
+    }
+}
+
+impl ArrayField {
+    // Split a string field into an array of its trimmed, non-empty values
+    //
+    // A single value becomes a one element array and an empty string an empty array, so the
+    // field always has the same type. Fields made null by the null handling are left as null
+    pub fn apply(&self, mut document: Document) -> Document {
+        if let Some(Bson::String(value)) = document.get(&self.field) {
+            let values: Vec<Bson> = value
+                .split(self.delimiter.as_str())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| Bson::String(value.to_string()))
+                .collect();
+
+            document.insert(self.field.clone(), Bson::Array(values));
+        }
+
+        document
+    }
+}
+
+// The options applied to every record when it is serialized
+#[derive(Clone, Default)]
+pub struct OutputOptions {
+    pub null_handling: NullHandling,
+    pub array_fields: Vec<ArrayField>,
+    pub compress_raw: bool,
+    // The same time is stamped on every record, so a whole run can be found by it
+    pub ingested_at: Option<bson::DateTime>,
+    pub field_naming: FieldNaming,
+    // Stored names for individual fields, from the [output_fields] table of the config file
+    pub field_renames: HashMap<String, String>,
+}
+
+impl OutputOptions {
+    // Whether the record can be serialized as it is
+    fn is_unchanged(&self) -> bool {
+        self.null_handling == NullHandling::KeepEmpty
+            && self.array_fields.is_empty()
+            && self.ingested_at.is_none()
+            && !self.renames_fields()
+    }
+
+    fn renames_fields(&self) -> bool {
+        self.field_naming != FieldNaming::CamelCase || !self.field_renames.is_empty()
+    }
+
+    // The name a field of the model is stored under
+    pub fn stored_name(&self, field: &str) -> String {
+        match self.field_renames.get(field) {
+            Some(stored_name) => stored_name.clone(),
+            None => self.field_naming.apply(field),
+        }
+    }
+
+    fn apply(&self, document: Document) -> Document {
+        // Apply the null handling first so that omitted and null fields are not split
+        let document: Document = self.null_handling.apply(document);
+
+        // The array fields are named as in the model, so rename the fields last
+        let document: Document = self
+            .array_fields
+            .iter()
+            .fold(document, |document, array_field| array_field.apply(document));
+
+        match self.renames_fields() {
+            true => document.into_iter().map(|(key, value)| (self.stored_name(&key), value)).collect(),
+            false => document,
+        }
+    }
+
+    // The value stored for the source line, plain or gzip compressed
+    fn raw_value(&self, raw: &str) -> std::io::Result<Bson> {
+        match self.compress_raw {
+            true => Ok(Bson::Binary(compress_raw(raw)?)),
+            false => Ok(Bson::String(raw.to_string())),
+        }
+    }
+}
+
+// Gzip a source line into an opaque BSON binary
+pub fn compress_raw(raw: &str) -> std::io::Result<Binary> {
+    let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(raw.as_bytes())?;
+
+    Ok(Binary {
+        subtype: BinarySubtype::Generic,
+        bytes: encoder.finish()?,
+    })
+}
+
+// Recover a source line stored with --compress-raw
+pub fn decompress_raw(binary: &Binary) -> std::io::Result<String> {
+    let mut raw: String = String::new();
+    GzDecoder::new(binary.bytes.as_slice()).read_to_string(&mut raw)?;
+    Ok(raw)
+}
+
+// Wraps a record so that the output options are applied when it is serialized
+pub struct OutputRecord<T> {
+    pub record: T,
+    raw: Option<String>,
+    options: Arc<OutputOptions>,
+    // Returned to --memory-budget once the record has been stored and dropped
+    memory: Option<MemoryPermit>,
+}
+
+impl<T> OutputRecord<T> {
+    pub fn new(record: T, options: Arc<OutputOptions>) -> Self {
+        OutputRecord {
+            record,
+            raw: None,
+            options,
+            memory: None,
+        }
+    }
+
+    pub fn with_memory(mut self, memory: Option<MemoryPermit>) -> Self {
+        // Hold the record's share of the memory budget until it is dropped
+        self.memory = memory;
+        self
+    }
+
+    pub fn with_raw(mut self, raw: Option<String>) -> Self {
+        // Store the source line alongside the record
+        self.raw = raw;
+        self
+    }
+}
+
+impl<T> Serialize for OutputRecord<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Nothing to change, serialize the record directly
+        if self.raw.is_none() && self.options.is_unchanged() {
+            return self.record.serialize(serializer);
+        }
+
+        // Convert the record to a document so the fields can be inspected
+        let document: Document = bson::to_document(&self.record).map_err(S::Error::custom)?;
+
+        // Apply the output options
+        let mut document: Document = self.options.apply(document);
+
+        // Add the source line after the options, so it is never split or omitted
+        if let Some(raw) = &self.raw {
+            document.insert(RAW_FIELD, self.options.raw_value(raw).map_err(S::Error::custom)?);
+        }
+
+        // Stamp the start of the run, as a BSON datetime so it can be queried by date
+        if let Some(ingested_at) = self.options.ingested_at {
+            document.insert(INGESTED_AT_FIELD, Bson::DateTime(ingested_at));
+        }
+
+        document.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::*;
+
+    fn sample() -> Document {
+        doc! { "icao24": "4CA1FA", "owner": "", "model": "A320-214" }
+    }
+
+    #[test]
+    fn keep_empty_leaves_the_document_unchanged() {
+        assert_eq!(NullHandling::KeepEmpty.apply(sample()), sample());
+    }
+
+    #[test]
+    fn omit_removes_empty_fields() {
+        let document: Document = NullHandling::Omit.apply(sample());
+
+        assert_eq!(document, doc! { "icao24": "4CA1FA", "model": "A320-214" });
+    }
+
+    #[test]
+    fn null_replaces_empty_fields() {
+        let document: Document = NullHandling::Null.apply(sample());
+
+        assert_eq!(document.get("owner"), Some(&Bson::Null));
+        assert_eq!(document.get_str("icao24"), Ok("4CA1FA"));
+        assert_eq!(document.len(), 3);
+    }
+
+    #[test]
+    fn only_empty_strings_are_changed() {
+        let document: Document = doc! { "engines": 0, "owner": " ", "built": Bson::Null };
+
+        assert_eq!(NullHandling::Omit.apply(document.clone()), document);
+        assert_eq!(NullHandling::Null.apply(document.clone()), document);
+    }
+
+    fn split(value: &str) -> Bson {
+        let array_field: ArrayField = "prevReg:,".parse().unwrap();
+        let document: Document = array_field.apply(doc! { "prevReg": value, "owner": "a,b" });
+
+        // Only the named field is split
+        assert_eq!(document.get_str("owner"), Ok("a,b"));
+
+        document.get("prevReg").unwrap().clone()
+    }
+
+    #[test]
+    fn splits_multiple_values() {
+        assert_eq!(split("G-ABCD, G-EFGH,,G-IJKL "), Bson::from(vec!["G-ABCD", "G-EFGH", "G-IJKL"]));
+    }
+
+    #[test]
+    fn single_values_become_one_element_arrays() {
+        assert_eq!(split("G-ABCD"), Bson::from(vec!["G-ABCD"]));
+        assert_eq!(split(""), Bson::Array(Vec::new()));
+    }
+
+    #[test]
+    fn null_fields_are_not_split() {
+        let array_field: ArrayField = "prevReg:;".parse().unwrap();
+
+        assert_eq!(array_field.apply(doc! { "prevReg": Bson::Null }), doc! { "prevReg": Bson::Null });
+        assert_eq!(array_field.apply(doc! {}), doc! {});
+    }
+
+    #[test]
+    fn parses_field_and_delimiter() {
+        let array_field: ArrayField = "modes:; ".parse().unwrap();
+        assert_eq!(array_field.field, "modes");
+        assert_eq!(array_field.delimiter, "; ");
+
+        assert!("modes".parse::<ArrayField>().is_err());
+        assert!(":,".parse::<ArrayField>().is_err());
+        assert!("modes:".parse::<ArrayField>().is_err());
+    }
+
+    #[test]
+    fn every_record_is_stamped_with_the_same_datetime() {
+        let ingested_at: bson::DateTime = bson::DateTime::from_millis(1_700_000_000_000);
+        let options: Arc<OutputOptions> = Arc::new(OutputOptions {
+            ingested_at: Some(ingested_at),
+            ..OutputOptions::default()
+        });
+
+        for record in [sample(), doc! { "icao24": "A1B2C3" }] {
+            let document: Document = bson::to_document(&OutputRecord::new(record, options.clone())).unwrap();
+
+            assert_eq!(document.get(INGESTED_AT_FIELD), Some(&Bson::DateTime(ingested_at)));
+            assert_eq!(document.get_datetime(INGESTED_AT_FIELD), Ok(&ingested_at));
+        }
+
+        // Without the option there is no stamp
+        let document: Document = bson::to_document(&OutputRecord::new(sample(), Arc::new(OutputOptions::default()))).unwrap();
+        assert!(!document.contains_key(INGESTED_AT_FIELD));
+    }
+
+    #[test]
+    fn stored_documents_use_the_renamed_keys() {
+        let options: Arc<OutputOptions> = Arc::new(OutputOptions {
+            array_fields: vec!["prevReg:,".parse().unwrap()],
+            field_naming: FieldNaming::SnakeCase,
+            field_renames: HashMap::from([("icao24".to_string(), "hex".to_string())]),
+            ..OutputOptions::default()
+        });
+
+        let record: Document = doc! { "icao24": "4CA1FA", "manufacturerName": "Airbus", "prevReg": "G-ABCD", "ageYears": 21 };
+        let document: Document = bson::to_document(&OutputRecord::new(record, options.clone()).with_raw(Some("4CA1FA".to_string()))).unwrap();
+
+        // The array field is named as in the model, and the raw line keeps its name
+        assert_eq!(
+            document,
+            doc! { "hex": "4CA1FA", "manufacturer_name": "Airbus", "prev_reg": ["G-ABCD"], "age_years": 21, "_raw": "4CA1FA" }
+        );
+
+        assert_eq!(options.stored_name("operatorIata"), "operator_iata");
+        assert_eq!(options.stored_name("icao24"), "hex");
+        assert_eq!(FieldNaming::CamelCase.apply("operatorIata"), "operatorIata");
+    }
+
+    #[test]
+    fn compressed_lines_round_trip() {
+        let raw: &str = "'4ca1fa','2024-01-02 10:00:00','','','2005-03-01','','Ireland','2'";
+
+        let binary: Binary = compress_raw(raw).unwrap();
+
+        assert_eq!(binary.subtype, BinarySubtype::Generic);
+        assert_ne!(binary.bytes, raw.as_bytes());
+        assert_eq!(decompress_raw(&binary).unwrap(), raw);
+        assert_eq!(decompress_raw(&compress_raw("").unwrap()).unwrap(), "");
+    }
+}
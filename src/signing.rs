@@ -0,0 +1,143 @@
+use clap::ValueEnum;
+
+use hmac::{Hmac, KeyInit, Mac};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Url;
+
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+// The header the signature is sent in unless --hmac-header is given
+pub const DEFAULT_HMAC_HEADER: &str = "X-Signature";
+
+// The digest the HMAC is built on, which has to match the mirror
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Sha512,
+}
+
+// Signs each download request with an HMAC of its path, for mirrors that need a shared secret
+#[derive(Clone)]
+pub struct RequestSigner {
+    secret: String,
+    header: String,
+    algorithm: HmacAlgorithm,
+}
+
+// The secret is never printed, so a signer can be logged safely
+impl std::fmt::Debug for RequestSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RequestSigner")
+            .field("secret", &"<redacted>")
+            .field("header", &self.header)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl RequestSigner {
+    pub fn new(secret: String, header: String, algorithm: HmacAlgorithm) -> Self {
+        RequestSigner { secret, header, algorithm }
+    }
+
+    // Check the signer can be used before anything is downloaded
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.is_empty() {
+            return Err("--hmac-secret can't be empty".to_string());
+        }
+
+        match HeaderName::from_bytes(self.header.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("--hmac-header {} is not a valid header name", self.header)),
+        }
+    }
+
+    // The signature of the request for this URL as lowercase hex
+    pub fn signature(&self, url: &str) -> Result<String, String> {
+        Ok(self.sign(&signing_string(url)?))
+    }
+
+    // The header to send with every request for this URL, marked sensitive so it isn't logged
+    pub fn headers(&self, url: &str) -> Result<HeaderMap, String> {
+        let name: HeaderName = HeaderName::from_bytes(self.header.as_bytes()).map_err(|error| error.to_string())?;
+        let mut value: HeaderValue = HeaderValue::from_str(&self.signature(url)?).map_err(|error| error.to_string())?;
+        value.set_sensitive(true);
+
+        let mut headers: HeaderMap = HeaderMap::new();
+        headers.insert(name, value);
+
+        Ok(headers)
+    }
+
+    fn sign(&self, signing_string: &str) -> String {
+        let digest: Vec<u8> = match self.algorithm {
+            HmacAlgorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(self.secret.as_bytes(), signing_string),
+            HmacAlgorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(self.secret.as_bytes(), signing_string),
+            HmacAlgorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(self.secret.as_bytes(), signing_string),
+        };
+
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+fn hmac_digest<M>(secret: &[u8], signing_string: &str) -> Vec<u8>
+where
+    M: Mac + KeyInit,
+{
+    // HMAC takes a key of any length, so this can't fail
+    let mut mac: M = <M as KeyInit>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(signing_string.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// The string that is signed, the path of the URL followed by its query if it has one
+fn signing_string(url: &str) -> Result<String, String> {
+    let url: Url = Url::parse(url).map_err(|error| format!("could not sign {}: {}", url, error))?;
+
+    Ok(match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_match_the_rfc_4231_vectors() {
+        let signer: RequestSigner = RequestSigner::new("Jefe".to_string(), DEFAULT_HMAC_HEADER.to_string(), HmacAlgorithm::Sha256);
+        assert_eq!(signer.sign("what do ya want for nothing?"), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        let signer: RequestSigner = RequestSigner::new("Jefe".to_string(), DEFAULT_HMAC_HEADER.to_string(), HmacAlgorithm::Sha1);
+        assert_eq!(signer.sign("what do ya want for nothing?"), "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+    }
+
+    #[test]
+    fn the_path_and_query_are_signed() {
+        assert_eq!(signing_string("https://mirror.example/data/aircraft.csv").unwrap(), "/data/aircraft.csv");
+        assert_eq!(signing_string("https://mirror.example/data/aircraft.csv?month=2024-05").unwrap(), "/data/aircraft.csv?month=2024-05");
+        assert!(signing_string("not a url").is_err());
+    }
+
+    #[test]
+    fn the_secret_is_kept_out_of_the_debug_output() {
+        let signer: RequestSigner = RequestSigner::new("hunter2".to_string(), "X-Mirror-Signature".to_string(), HmacAlgorithm::Sha512);
+        assert!(!format!("{:?}", signer).contains("hunter2"));
+
+        let headers: HeaderMap = signer.headers("https://mirror.example/aircraft.csv").unwrap();
+        assert!(headers["x-mirror-signature"].is_sensitive());
+        assert_eq!(headers["x-mirror-signature"].len(), 128);
+    }
+
+    #[test]
+    fn bad_header_names_and_empty_secrets_are_rejected() {
+        assert!(RequestSigner::new("secret".to_string(), "X Signature".to_string(), HmacAlgorithm::Sha256).validate().is_err());
+        assert!(RequestSigner::new(String::new(), DEFAULT_HMAC_HEADER.to_string(), HmacAlgorithm::Sha256).validate().is_err());
+        assert!(RequestSigner::new("secret".to_string(), DEFAULT_HMAC_HEADER.to_string(), HmacAlgorithm::Sha256).validate().is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+use std::future::Future;
+
+use serde::Serialize;
+
+use crate::db_writer::{DatabaseError, DatabaseWriter};
+use crate::file_writer::{JsonLinesWriter, OutputError};
+
+// Errors that can occur when sending a record to a sink
+#[derive(Debug)]
+pub enum SinkError {
+    DatabaseError(DatabaseError),
+    OutputError(OutputError),
+    // More distinct values than the limit
+    TooManyValues(usize),
+}
+
+impl From<DatabaseError> for SinkError {
+    fn from(error: DatabaseError) -> Self {
+        SinkError::DatabaseError(error)
+    }
+}
+
+impl From<OutputError> for SinkError {
+    fn from(error: OutputError) -> Self {
+        SinkError::OutputError(error)
+    }
+}
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SinkError::DatabaseError(error) => write!(f, "{}", error),
+            SinkError::OutputError(error) => write!(f, "{}", error),
+            SinkError::TooManyValues(limit) => write!(f, "more than {} distinct values, raise --limit to list them all", limit),
+        }
+    }
+}
+
+// The message is the database error's own, so its causes follow on from it
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SinkError::DatabaseError(error) => std::error::Error::source(error),
+            SinkError::OutputError(_) | SinkError::TooManyValues(_) => None,
+        }
+    }
+}
+
+// Somewhere the processed records are sent
+pub trait RecordSink<T> {
+    fn add_record(&mut self, record: T) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    // Send on any records held back in a partly filled batch, when --memory-budget is used up
+    fn flush_partial(&mut self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    // Clean up after the run failed partway, so the partial output isn't taken for a complete one,
+    // a sink that keeps nothing outside the process has nothing to undo
+    fn abort(&mut self) -> impl Future<Output = Result<(), SinkError>> + Send {
+        async { Ok(()) }
+    }
+}
+
+impl<T> RecordSink<T> for DatabaseWriter<T>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    async fn add_record(&mut self, record: T) -> Result<(), SinkError> {
+        DatabaseWriter::add_record(self, record).await;
+        Ok(())
+    }
+
+    async fn flush_partial(&mut self) {
+        DatabaseWriter::flush_partial(self).await;
+    }
+
+    // Only the records not yet sent are dropped, the chunks already inserted stay in the
+    // collection, which --staging keeps apart from the live one
+    async fn abort(&mut self) -> Result<(), SinkError> {
+        self.discard_pending();
+        Ok(())
+    }
+}
+
+impl<T> RecordSink<T> for JsonLinesWriter
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    async fn add_record(&mut self, record: T) -> Result<(), SinkError> {
+        self.write_record(&record).await?;
+        Ok(())
+    }
+
+    async fn abort(&mut self) -> Result<(), SinkError> {
+        JsonLinesWriter::abort(self).await?;
+        Ok(())
+    }
+}
+
+// Discards every record, for a --dry-run that only parses and counts them
+pub struct DryRunSink;
+
+impl<T> RecordSink<T> for DryRunSink
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    async fn add_record(&mut self, _record: T) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+// A secondary sink for the records that are filtered out, a JSON lines file or a collection
+pub enum FilteredOutSink<T>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    File(JsonLinesWriter),
+    Collection(DatabaseWriter<T>),
+}
+
+impl<T> RecordSink<T> for FilteredOutSink<T>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    async fn add_record(&mut self, record: T) -> Result<(), SinkError> {
+        match self {
+            FilteredOutSink::File(file_writer) => RecordSink::add_record(file_writer, record).await,
+            FilteredOutSink::Collection(db_writer) => RecordSink::add_record(db_writer, record).await,
+        }
+    }
+
+    async fn abort(&mut self) -> Result<(), SinkError> {
+        match self {
+            FilteredOutSink::File(file_writer) => RecordSink::<T>::abort(file_writer).await,
+            FilteredOutSink::Collection(db_writer) => RecordSink::abort(db_writer).await,
+        }
+    }
+}
+
+impl<T> FilteredOutSink<T>
+where
+    T: Send + Sync + Serialize + 'static,
+{
+    pub async fn finish(&mut self) -> Result<(), SinkError> {
+        match self {
+            FilteredOutSink::File(file_writer) => Ok(file_writer.finish().await?),
+            FilteredOutSink::Collection(db_writer) => {
+                // Wait for the remaining inserts, the progress isn't shown for the secondary sink
+                let mut channel = db_writer.finish().await;
+                let mut result: Result<(), SinkError> = Ok(());
+
+                while let Some(chunk_result) = channel.recv().await {
+                    if let (Err(error), Ok(_)) = (chunk_result, &result) {
+                        result = Err(error.into());
+                    }
+                }
+
+                result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Document};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn a_failed_file_sink_is_cleaned_up_through_the_trait() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filtered_out.jsonl");
+        let mut sink: FilteredOutSink<Document> = FilteredOutSink::File(JsonLinesWriter::new(&path, None));
+
+        sink.add_record(doc! { "icao24": "" }).await.unwrap();
+        assert!(path.exists());
+
+        sink.abort().await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn a_sink_that_cant_write_reports_the_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink: FilteredOutSink<Document> = FilteredOutSink::File(JsonLinesWriter::new(&dir.path().join("missing").join("out.jsonl"), None));
+
+        let result = sink.add_record(doc! { "icao24": "" }).await;
+        assert!(matches!(result, Err(SinkError::OutputError(_))));
+        assert!(sink.abort().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn an_empty_filtered_out_collection_finishes_cleanly() {
+        // Nothing was filtered out, so nothing is sent and the missing server isn't reached
+        let mut sink: FilteredOutSink<Document> = FilteredOutSink::Collection(DatabaseWriter::unreachable(2).await);
+        assert!(sink.finish().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn sinks_without_output_have_nothing_to_undo() {
+        assert!(RecordSink::<Document>::abort(&mut DryRunSink).await.is_ok());
+    }
+}
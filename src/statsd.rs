@@ -0,0 +1,149 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::task::JoinHandle;
+
+use crate::metrics::{metrics, MetricsSnapshot};
+
+// Every metric name starts with this, e.g. opensky_downloader.records.read
+pub const METRIC_PREFIX: &str = "opensky_downloader";
+
+// How often the counters are sent while the run goes on
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Sends the run's counters to a StatsD server over UDP, as the growth since the last flush
+//
+// StatsD is fire and forget, a packet that can't be sent is dropped rather than failing the run
+pub struct StatsdReporter {
+    socket: Arc<UdpSocket>,
+    sent: Arc<Mutex<MetricsSnapshot>>,
+    task: JoinHandle<()>,
+}
+
+impl StatsdReporter {
+    // Resolve the address and start flushing the counters periodically
+    pub async fn start(addr: &str) -> std::io::Result<Self> {
+        let target: SocketAddr = lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} did not resolve to an address", addr)))?;
+
+        // Bind to any local address of the same family as the server
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+
+        let socket: Arc<UdpSocket> = Arc::new(UdpSocket::bind(local).await?);
+        socket.connect(target).await?;
+
+        let sent: Arc<Mutex<MetricsSnapshot>> = Arc::new(Mutex::new(MetricsSnapshot::default()));
+
+        let task: JoinHandle<()> = tokio::spawn({
+            let socket: Arc<UdpSocket> = socket.clone();
+            let sent: Arc<Mutex<MetricsSnapshot>> = sent.clone();
+
+            async move {
+                let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+                    flush(&socket, &sent, Vec::new()).await;
+                }
+            }
+        });
+
+        Ok(StatsdReporter { socket, sent, task })
+    }
+
+    // Stop the periodic flushes and send what is left, with the run time and exit code
+    pub async fn finish(self, duration: Duration, exit_code: i32) {
+        self.task.abort();
+
+        let lines: Vec<String> = vec![
+            format!("{}.duration:{}|ms", METRIC_PREFIX, duration.as_millis()),
+            format!("{}.exit_code:{}|g", METRIC_PREFIX, exit_code),
+        ];
+
+        flush(&self.socket, &self.sent, lines).await;
+    }
+}
+
+// Send the growth of the counters since the last flush, along with any other lines
+async fn flush(socket: &UdpSocket, sent: &Mutex<MetricsSnapshot>, mut lines: Vec<String>) {
+    let now: MetricsSnapshot = metrics().snapshot();
+
+    let growth: MetricsSnapshot = {
+        let mut sent = sent.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let growth: MetricsSnapshot = now.since(&sent);
+        *sent = now;
+        growth
+    };
+
+    lines.extend(counter_lines(&growth));
+
+    // One packet for the lot, StatsD reads a metric from each line
+    if !lines.is_empty() {
+        let _ = socket.send(lines.join("\n").as_bytes()).await;
+    }
+}
+
+// A StatsD counter line for each counter that has grown
+fn counter_lines(growth: &MetricsSnapshot) -> Vec<String> {
+    let counters: [(&str, u64); 5] = [
+        ("records.read", growth.records_read),
+        ("records.stored", growth.records_stored),
+        ("bytes.read", growth.bytes_read),
+        ("errors", growth.errors),
+        ("warnings", growth.warnings),
+    ];
+
+    counters
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| format!("{}.{}:{}|c", METRIC_PREFIX, name, count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_counters_that_grew_are_sent() {
+        let growth: MetricsSnapshot = MetricsSnapshot {
+            records_read: 1000,
+            bytes_read: 250_000,
+            ..MetricsSnapshot::default()
+        };
+
+        assert_eq!(
+            counter_lines(&growth),
+            ["opensky_downloader.records.read:1000|c", "opensky_downloader.bytes.read:250000|c"]
+        );
+        assert!(counter_lines(&MetricsSnapshot::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_run_time_and_exit_code_are_sent_at_the_end() {
+        let server: UdpSocket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr: String = server.local_addr().unwrap().to_string();
+
+        let reporter: StatsdReporter = StatsdReporter::start(&addr).await.unwrap();
+        reporter.finish(Duration::from_millis(1500), 2).await;
+
+        // The first packet is the periodic flush at the start, which may be empty and not sent
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut packet: String = String::new();
+
+        while !packet.contains("duration") {
+            let length: usize = tokio::time::timeout(Duration::from_secs(1), server.recv(&mut buffer)).await.unwrap().unwrap();
+            packet = String::from_utf8_lossy(&buffer[..length]).into_owned();
+        }
+
+        assert!(packet.contains("opensky_downloader.duration:1500|ms"));
+        assert!(packet.contains("opensky_downloader.exit_code:2|g"));
+    }
+}
@@ -0,0 +1,691 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use colored::Colorize;
+
+use indicatif::HumanBytes;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::csv_dialect::DatasetProfile;
+use crate::metrics::MetricsSnapshot;
+use crate::ExitCodes;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    /// Coloured lines for a person to read
+    #[default]
+    #[value(alias = "human")]
+    Lines,
+
+    /// An aligned table of every statistic, easier to scan
+    Table,
+
+    /// A single line JSON object, for scripts and log processing
+    Json,
+}
+
+// The smallest and largest values seen for a field, compared as strings
+//
+// OpenSky dates are ISO 8601, so lexicographic order is chronological order
+#[derive(Default, Serialize)]
+pub struct ValueRange {
+    min: Option<String>,
+    max: Option<String>,
+}
+
+impl ValueRange {
+    pub fn update(&mut self, value: &str) {
+        // Blank values are common and carry no information
+        if value.is_empty() {
+            return;
+        }
+
+        if self.min.as_deref().is_none_or(|min| value < min) {
+            self.min = Some(value.to_string());
+        }
+
+        if self.max.as_deref().is_none_or(|max| value > max) {
+            self.max = Some(value.to_string());
+        }
+    }
+
+    pub fn min(&self) -> Option<&str> {
+        self.min.as_deref()
+    }
+
+    pub fn max(&self) -> Option<&str> {
+        self.max.as_deref()
+    }
+}
+
+// Statistics gathered while the records are processed
+//
+// The counters are None when the option that produces them wasn't used, and are left out of the JSON
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    // Nothing was written, the stored count is what would have been
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    // Records received from the source and sent to the collection or file
+    pub records_read: u64,
+    pub records_stored: u64,
+    // The --limit that stopped the run, only set if it was reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    pub duration_seconds: f64,
+    pub records_per_second: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_read: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    // Only the aircraft have timestamps and built dates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<ValueRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub built: Option<ValueRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enriched: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub empty_icao24: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_empty_icao24: Option<u64>,
+    // Set with --dataset airports in place of the empty icao24 count
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_empty_ident: Option<u64>,
+    // Set with --country
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_country: Option<u64>,
+    // Set with --pad-icao
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padded_icao24: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invalid_icao24: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_errors: Option<u64>,
+    // Set with --quote-fallback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovered_records: Option<u64>,
+    // Only set when inserts failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_chunks: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_documents: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filtered_out: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<u64>,
+    // The most the records held at once were estimated to use, against --memory-budget
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_budget_bytes: Option<u64>,
+    // The names of the fields that were truncated, in the order first seen
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub truncated_fields: Vec<&'static str>,
+}
+
+impl Summary {
+    // An empty summary, with the counters for the options in use set to zero
+    pub fn for_config(config: &Config) -> Self {
+        let airports: bool = config.dataset == DatasetProfile::Airports;
+
+        Summary {
+            timestamp: (!airports).then(ValueRange::default),
+            built: (!airports).then(ValueRange::default),
+            enriched: config.enrich_file.as_ref().map(|_| 0),
+            duplicates: (!config.dedup_key.is_empty()).then_some(0),
+            filtered_out: config.filtered_out.as_ref().map(|_| 0),
+            truncated: config.max_field_length.map(|_| 0),
+            dry_run: config.dry_run,
+            skipped_empty_icao24: (!airports).then_some(0),
+            skipped_empty_ident: airports.then_some(0),
+            skipped_country: (!config.countries.is_empty()).then_some(0),
+            padded_icao24: config.pad_icao24.then_some(0),
+            invalid_icao24: config.pad_icao24.then_some(0),
+            ..Default::default()
+        }
+    }
+
+    // Count a record the model's filter skipped, an empty icao24 for aircraft or an empty ident for airports
+    pub fn add_filtered(&mut self) {
+        if let Some(skipped) = self.skipped_empty_icao24.as_mut().or(self.skipped_empty_ident.as_mut()) {
+            *skipped += 1;
+        }
+    }
+
+    // Count the fields cut from one record
+    pub fn add_truncated(&mut self, fields: Vec<&'static str>) {
+        if let Some(truncated) = self.truncated.as_mut() {
+            *truncated += fields.len() as u64;
+        }
+
+        for field in fields {
+            if !self.truncated_fields.contains(&field) {
+                self.truncated_fields.push(field);
+            }
+        }
+    }
+
+    // Count the chunks that failed to insert, whose documents were sent but not stored
+    pub fn add_insert_failures(&mut self, chunks: u64, documents: u64) {
+        *self.failed_chunks.get_or_insert(0) += chunks;
+        *self.failed_documents.get_or_insert(0) += documents;
+        self.records_stored = self.records_stored.saturating_sub(documents);
+    }
+
+    pub fn set_memory_use(&mut self, peak_bytes: u64, budget_bytes: u64) {
+        self.peak_memory_bytes = Some(peak_bytes);
+        self.memory_budget_bytes = Some(budget_bytes);
+    }
+
+    // Set how long the run took and the throughput of the stored records
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_seconds = duration.as_secs_f64();
+
+        if self.duration_seconds > 0.0 {
+            self.records_per_second = self.records_stored as f64 / self.duration_seconds;
+        }
+    }
+
+    pub fn print(&self, format: SummaryFormat) {
+        match format {
+            SummaryFormat::Lines => self.print_lines(),
+            SummaryFormat::Table => self.print_table(),
+            SummaryFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(error) => {
+                    let text = format!("Warning: could not write the summary as JSON: {}", error);
+                    crate::report::warning(&text);
+                }
+            },
+        }
+    }
+
+    fn print_lines(&self) {
+        let text: String = match self.dry_run {
+            true => format!(
+                "Read {} records in {:.2}s, {} would have been stored",
+                self.records_read, self.duration_seconds, self.records_stored
+            ),
+            false => format!(
+                "Read {} records and stored {} in {:.2}s, {:.0} records per second",
+                self.records_read, self.records_stored, self.duration_seconds, self.records_per_second
+            ),
+        };
+        println!("{}", text.blue().bold());
+
+        // The rest of the source wasn't read, so the counts are only for the first records
+        if let Some(limit) = self.limit {
+            let text: String = format!("Stopped after the first {} records (--limit)", limit);
+            println!("{}", text.blue().bold());
+        }
+
+        // The content length is only known for downloads and files
+        match (self.bytes_read, self.content_length) {
+            (Some(bytes_read), Some(content_length)) => {
+                let text: String = format!("Read {} bytes of a {} byte content length", bytes_read, content_length);
+                println!("{}", text.blue().bold());
+            }
+            (Some(bytes_read), None) => {
+                let text: String = format!("Read {} bytes", bytes_read);
+                println!("{}", text.blue().bold());
+            }
+            _ => {}
+        }
+
+        if let Some(timestamp) = &self.timestamp {
+            print_range("Timestamps", timestamp);
+        }
+
+        if let Some(built) = &self.built {
+            print_range("Built", built);
+        }
+
+        // Only report enrichment if a lookup file was used
+        if let Some(enriched) = self.enriched {
+            let text: String = format!("Enriched {} records from the lookup file", enriched);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report duplicates if deduplication was used
+        if let Some(duplicates) = self.duplicates {
+            let text: String = format!("Dropped {} duplicate records", duplicates);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the secondary sink if there was one
+        if let Some(filtered_out) = self.filtered_out {
+            let text: String = format!("Sent {} filtered out records to the secondary sink", filtered_out);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report truncation if there was a limit
+        if let Some(truncated) = self.truncated {
+            let text: String = format!("Truncated {} long field values", truncated);
+            println!("{}", text.blue().bold());
+        }
+
+        if !self.truncated_fields.is_empty() {
+            let text: String = format!("Warning: truncated values in {}", self.truncated_fields.join(", "));
+            crate::report::warning(&text);
+        }
+
+        // Report the records the model doesn't store, a sudden rise means the source has changed
+        if let Some(skipped_empty_icao24) = self.skipped_empty_icao24 {
+            let text: String = format!("Skipped {} records with an empty icao24", skipped_empty_icao24);
+            println!("{}", text.blue().bold());
+        }
+
+        if let Some(skipped_empty_ident) = self.skipped_empty_ident {
+            let text: String = format!("Skipped {} airports with an empty ident", skipped_empty_ident);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the country filter if it was used
+        if let Some(skipped_country) = self.skipped_country {
+            let text: String = format!("Skipped {} records from other countries", skipped_country);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the padding if it was requested
+        if let (Some(padded_icao24), Some(invalid_icao24)) = (self.padded_icao24, self.invalid_icao24) {
+            let text: String = format!("Padded {} short icao24 addresses, skipped {} invalid ones", padded_icao24, invalid_icao24);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report insert failures if there were any
+        if let (Some(failed_chunks), Some(failed_documents)) = (self.failed_chunks, self.failed_documents) {
+            let text: String = format!("Failed to insert {} documents in {} chunks", failed_documents, failed_chunks);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the memory use if there was a budget
+        if let Some(text) = self.memory_text() {
+            let text: String = format!("Peak record memory {}", text);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report skipped records if they were allowed
+        if let Some(record_errors) = self.record_errors {
+            let text: String = format!("Skipped {} records that failed to parse", record_errors);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the quote fallback if it was used
+        if let Some(recovered_records) = self.recovered_records {
+            let text: String = format!("Recovered {} records by reading them without quoting", recovered_records);
+            println!("{}", text.blue().bold());
+        }
+
+        // Only report the consistency check if it was run
+        if let Some(empty_icao24) = self.empty_icao24 {
+            let text: String = format!("Found {} stored documents with an empty or missing icao24", empty_icao24);
+            println!("{}", text.blue().bold());
+        }
+    }
+
+    // The label and value of each statistic, leaving out the counters for unused options
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows: Vec<(&'static str, String)> = vec![
+            ("Records read", self.records_read.to_string()),
+            (
+                match self.dry_run {
+                    true => "Records to store",
+                    false => "Records stored",
+                },
+                self.records_stored.to_string(),
+            ),
+        ];
+
+        let counters: [(&'static str, Option<u64>); 17] = [
+            ("Stopped at limit", self.limit),
+            ("Bytes read", self.bytes_read),
+            ("Content length", self.content_length),
+            ("Skipped, empty icao24", self.skipped_empty_icao24),
+            ("Skipped, empty ident", self.skipped_empty_ident),
+            ("Skipped, other country", self.skipped_country),
+            ("Padded icao24", self.padded_icao24),
+            ("Skipped, invalid icao24", self.invalid_icao24),
+            ("Skipped, failed to parse", self.record_errors),
+            ("Recovered without quoting", self.recovered_records),
+            ("Failed chunks", self.failed_chunks),
+            ("Failed documents", self.failed_documents),
+            ("Dropped as duplicates", self.duplicates),
+            ("Filtered out", self.filtered_out),
+            ("Enriched", self.enriched),
+            ("Truncated values", self.truncated),
+            ("Stored with empty icao24", self.empty_icao24),
+        ];
+
+        rows.extend(counters.into_iter().filter_map(|(label, count)| count.map(|count| (label, count.to_string()))));
+
+        if let Some(text) = self.memory_text() {
+            rows.push(("Peak record memory", text));
+        }
+
+        if let Some(timestamp) = &self.timestamp {
+            rows.push(("Timestamps", range_text(timestamp)));
+        }
+
+        if let Some(built) = &self.built {
+            rows.push(("Built", range_text(built)));
+        }
+
+        rows.push(("Duration", format!("{:.2}s", self.duration_seconds)));
+        rows.push(("Throughput", format!("{:.0} records/s", self.records_per_second)));
+
+        rows
+    }
+
+    // The peak memory against the budget, e.g. "12.50 MiB of 64 MiB (20%)"
+    fn memory_text(&self) -> Option<String> {
+        let (peak_bytes, budget_bytes) = (self.peak_memory_bytes?, self.memory_budget_bytes?);
+        let percent: f64 = peak_bytes as f64 / budget_bytes.max(1) as f64 * 100.0;
+
+        Some(format!("{} of {} ({:.0}%)", HumanBytes(peak_bytes), HumanBytes(budget_bytes), percent))
+    }
+
+    fn print_table(&self) {
+        for line in table(&self.rows()) {
+            println!("{}", line);
+        }
+
+        if !self.truncated_fields.is_empty() {
+            let text = format!("Warning: truncated values in {}", self.truncated_fields.join(", "));
+            crate::report::warning(&text);
+        }
+    }
+}
+
+// The outcome of one dataset of a multi-dataset run, counted from the run metrics
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetSummary {
+    pub name: String,
+    pub collection: String,
+    pub exit_code: i32,
+    pub records_read: u64,
+    pub records_stored: u64,
+    pub errors: u64,
+    pub duration_seconds: f64,
+}
+
+impl DatasetSummary {
+    fn text(&self) -> String {
+        let text: String = format!(
+            "{} read, {} stored in {:.2}s",
+            self.records_read, self.records_stored, self.duration_seconds
+        );
+
+        match self.exit_code {
+            0 => text,
+            exit_code => format!("{}, failed with exit code {}", text, exit_code),
+        }
+    }
+}
+
+// Each dataset of a multi-dataset run and the totals across them
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetsSummary {
+    pub datasets: Vec<DatasetSummary>,
+    pub records_read: u64,
+    pub records_stored: u64,
+    pub errors: u64,
+    pub duration_seconds: f64,
+}
+
+impl DatasetsSummary {
+    pub fn new(datasets: Vec<DatasetSummary>, duration: Duration) -> Self {
+        DatasetsSummary {
+            records_read: datasets.iter().map(|dataset| dataset.records_read).sum(),
+            records_stored: datasets.iter().map(|dataset| dataset.records_stored).sum(),
+            errors: datasets.iter().map(|dataset| dataset.errors).sum(),
+            duration_seconds: duration.as_secs_f64(),
+            datasets,
+        }
+    }
+
+    fn total_text(&self) -> String {
+        format!(
+            "{} read, {} stored in {:.2}s",
+            self.records_read, self.records_stored, self.duration_seconds
+        )
+    }
+
+    fn rows(&self) -> Vec<(&str, String)> {
+        let mut rows: Vec<(&str, String)> = self
+            .datasets
+            .iter()
+            .map(|dataset| (dataset.name.as_str(), dataset.text()))
+            .collect();
+
+        rows.push(("Total", self.total_text()));
+
+        rows
+    }
+
+    pub fn print(&self, format: SummaryFormat) {
+        match format {
+            SummaryFormat::Lines => {
+                let text: String = format!("Loaded {} datasets, {}", self.datasets.len(), self.total_text());
+                println!("{}", text.yellow().bold());
+
+                for dataset in &self.datasets {
+                    let text: String = format!("{} into {}: {}", dataset.name, dataset.collection, dataset.text());
+                    println!("{}", text.blue().bold());
+                }
+            }
+            SummaryFormat::Table => {
+                for line in table(&self.rows()) {
+                    println!("{}", line);
+                }
+            }
+            SummaryFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(error) => {
+                    let text = format!("Warning: could not write the datasets summary as JSON: {}", error);
+                    crate::report::warning(&text);
+                }
+            },
+        }
+    }
+}
+
+// The outcome of a whole run, returned to library users rather than printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunSummary {
+    pub exit_code: ExitCodes,
+    pub records_read: u64,
+    pub records_stored: u64,
+    pub bytes_read: u64,
+    pub errors: u64,
+    pub warnings: u64,
+    pub duration: Duration,
+}
+
+impl RunSummary {
+    // The counts are the growth of the run metrics while the run went on
+    pub fn new(exit_code: ExitCodes, counted: MetricsSnapshot, duration: Duration) -> Self {
+        RunSummary {
+            exit_code,
+            records_read: counted.records_read,
+            records_stored: counted.records_stored,
+            bytes_read: counted.bytes_read,
+            errors: counted.errors,
+            warnings: counted.warnings,
+            duration,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == ExitCodes::Success
+    }
+}
+
+// Lay out label and value rows as a bordered table, with the labels left aligned and the values right aligned
+fn table(rows: &[(&str, String)]) -> Vec<String> {
+    let label_width: usize = rows.iter().map(|(label, _)| label.chars().count()).max().unwrap_or_default();
+    let value_width: usize = rows.iter().map(|(_, value)| value.chars().count()).max().unwrap_or_default();
+
+    let border: String = format!("+-{}-+-{}-+", "-".repeat(label_width), "-".repeat(value_width));
+
+    let mut lines: Vec<String> = vec![border.clone()];
+
+    for (label, value) in rows {
+        lines.push(format!("| {:<label_width$} | {:>value_width$} |", label, value));
+    }
+
+    lines.push(border);
+
+    lines
+}
+
+fn range_text(range: &ValueRange) -> String {
+    match (range.min(), range.max()) {
+        (Some(min), Some(max)) => format!("{} to {}", min, max),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn print_range(name: &str, range: &ValueRange) {
+    let text: String = match (range.min(), range.max()) {
+        (Some(min), Some(max)) => format!("{} range from {} to {}", name, min, max),
+        _ => format!("{} range unknown, no values found", name),
+    };
+
+    println!("{}", text.blue().bold());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let lines: Vec<String> = table(&[("Records read", "9000".to_string()), ("Built", "1970 to 2024".to_string())]);
+
+        assert_eq!(
+            lines,
+            [
+                "+--------------+--------------+",
+                "| Records read |         9000 |",
+                "| Built        | 1970 to 2024 |",
+                "+--------------+--------------+",
+            ]
+        );
+    }
+
+    #[test]
+    fn datasets_are_totalled() {
+        let dataset = |name: &str, exit_code: i32, records: u64| DatasetSummary {
+            name: name.to_string(),
+            collection: name.to_string(),
+            exit_code,
+            records_read: records,
+            records_stored: records,
+            errors: exit_code.min(1) as u64,
+            duration_seconds: 1.0,
+        };
+
+        let summary: DatasetsSummary = DatasetsSummary::new(vec![dataset("aircraft", 0, 90), dataset("mirror", 1, 10)], Duration::from_secs(2));
+
+        assert_eq!((summary.records_read, summary.records_stored, summary.errors), (100, 100, 1));
+        assert_eq!(
+            summary.rows(),
+            [
+                ("aircraft", "90 read, 90 stored in 1.00s".to_string()),
+                ("mirror", "10 read, 10 stored in 1.00s, failed with exit code 1".to_string()),
+                ("Total", "100 read, 100 stored in 2.00s".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_leave_out_unused_counters() {
+        let mut summary: Summary = Summary {
+            records_read: 10,
+            records_stored: 8,
+            duplicates: Some(2),
+            timestamp: Some(ValueRange::default()),
+            built: Some(ValueRange::default()),
+            ..Summary::default()
+        };
+        summary.set_duration(Duration::from_secs(4));
+
+        let rows: Vec<(&str, String)> = summary.rows();
+        let labels: Vec<&str> = rows.iter().map(|(label, _)| *label).collect();
+
+        assert_eq!(labels, ["Records read", "Records stored", "Dropped as duplicates", "Timestamps", "Built", "Duration", "Throughput"]);
+        assert_eq!(rows[6].1, "2 records/s");
+    }
+
+    #[test]
+    fn insert_failures_are_taken_off_the_stored_count() {
+        let mut summary: Summary = Summary {
+            records_read: 100,
+            records_stored: 100,
+            ..Summary::default()
+        };
+
+        summary.add_insert_failures(1, 3);
+        summary.add_insert_failures(2, 10);
+
+        assert_eq!((summary.failed_chunks, summary.failed_documents, summary.records_stored), (Some(3), Some(13), 87));
+
+        let labels: Vec<&str> = summary.rows().iter().map(|(label, _)| *label).collect();
+        assert!(labels.contains(&"Failed chunks") && labels.contains(&"Failed documents"));
+    }
+
+    #[test]
+    fn a_dry_run_counts_what_would_be_stored() {
+        let config: Config = Config {
+            dry_run: true,
+            ..Config::default()
+        };
+
+        let mut summary: Summary = Summary::for_config(&config);
+        summary.records_read = 4;
+        summary.records_stored = 3;
+        summary.skipped_empty_icao24 = Some(1);
+
+        let rows: Vec<(&str, String)> = summary.rows();
+        assert_eq!(&rows[..3], [("Records read", "4".to_string()), ("Records to store", "3".to_string()), ("Skipped, empty icao24", "1".to_string())]);
+
+        let json: serde_json::Value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["dryRun"], true);
+        assert_eq!(json["skippedEmptyIcao24"], 1);
+        assert!(serde_json::to_value(Summary::default()).unwrap().get("dryRun").is_none());
+    }
+
+    #[test]
+    fn every_run_counts_the_empty_icao24_records() {
+        let summary: Summary = Summary::for_config(&Config::default());
+        assert_eq!(summary.skipped_empty_icao24, Some(0));
+
+        let labels: Vec<&str> = summary.rows().iter().map(|(label, _)| *label).collect();
+        assert!(labels.contains(&"Skipped, empty icao24"));
+    }
+
+    #[test]
+    fn filtered_records_count_against_the_dataset_key() {
+        let mut summary: Summary = Summary::for_config(&Config::default());
+        summary.add_filtered();
+        assert_eq!((summary.skipped_empty_icao24, summary.skipped_empty_ident), (Some(1), None));
+
+        let config: Config = Config {
+            dataset: DatasetProfile::Airports,
+            ..Config::default()
+        };
+
+        let mut summary: Summary = Summary::for_config(&config);
+        summary.add_filtered();
+        assert_eq!((summary.skipped_empty_icao24, summary.skipped_empty_ident), (None, Some(1)));
+    }
+}
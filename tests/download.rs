@@ -0,0 +1,186 @@
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use opensky_downloader::config::{Config, Source};
+use opensky_downloader::models::{Aircraft, FilterMap};
+use opensky_downloader::progress::ProgressMode;
+use opensky_downloader::record_downloader::{DownloadError, DownloadInfo, RecordInfo};
+use opensky_downloader::run_with_summary;
+use opensky_downloader::summary::RunSummary;
+
+// Three records in the OpenSky layout, single quoted with a doubled quote inside one field, the
+// second has an empty icao24
+const FIXTURE: &str = include_str!("data/aircraft.csv");
+
+// Serve the body to every request on a local port, returning the URL to download it from
+async fn serve(body: String) -> String {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            // Read the request head, the requests have no body
+            let mut request: Vec<u8> = Vec::new();
+            let mut buffer = [0u8; 1024];
+
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match socket.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+
+            let response: String = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+
+    format!("http://{}/aircraftDatabase.csv", address)
+}
+
+// Download the URL, returning the records received and how the download ended
+async fn download(url: &str) -> (Vec<RecordInfo<Aircraft>>, Result<(), DownloadError<Aircraft>>) {
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+    download_info.set_stall_timeout(Some(Duration::from_secs(10)));
+
+    let join_handle = download_info.download(url).await.unwrap();
+
+    let mut records: Vec<RecordInfo<Aircraft>> = Vec::new();
+
+    while let Some(record_info) = download_info.rx_channel.recv().await {
+        records.push(record_info);
+    }
+
+    (records, join_handle.await.expect("the record task panicked"))
+}
+
+// A field of a record as it would be stored
+fn field(record_info: &RecordInfo<Aircraft>, name: &str) -> String {
+    let value: serde_json::Value = serde_json::to_value(&record_info.record).unwrap();
+    value[name].as_str().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn records_are_read_from_the_server_with_their_positions() {
+    let url: String = serve(FIXTURE.to_string()).await;
+    let (records, result) = download(&url).await;
+
+    result.unwrap();
+
+    // Every record is sent on, the empty icao24 is skipped by the loader rather than the downloader
+    let icao24s: Vec<String> = records.iter().map(|record_info| field(record_info, "icao24")).collect();
+    assert_eq!(icao24s, ["4ca1fa", "", "a0b1c2"]);
+
+    // The position of a record is how far the reader had got before it, the \n of a \r\n is read
+    // with the next record, so each is a byte short of the start of its line
+    let positions: Vec<u64> = records.iter().map(|record_info| record_info.position).collect();
+    assert_eq!(positions, [380, 568, 697]);
+
+    // The doubled quote is read as one quote, and the comma inside the quotes doesn't split the field
+    assert_eq!(field(&records[2], "operator"), "O'Hare Charter, Inc.");
+    assert_eq!(field(&records[2], "model"), "737-8H4");
+    assert!(records.iter().all(|record_info| record_info.raw.is_none()));
+}
+
+#[tokio::test]
+async fn the_progress_callback_reaches_the_content_length() {
+    let url: String = serve(FIXTURE.to_string()).await;
+
+    let reports: Arc<Mutex<Vec<(u64, u64)>>> = Arc::new(Mutex::new(Vec::new()));
+    let recorded: Arc<Mutex<Vec<(u64, u64)>>> = reports.clone();
+
+    let mut download_info: DownloadInfo<Aircraft> = DownloadInfo::new();
+    let join_handle = download_info
+        .download_with_progress(&url, move |done, total| recorded.lock().unwrap().push((done, total)))
+        .await
+        .unwrap();
+
+    while download_info.rx_channel.recv().await.is_some() {}
+    join_handle.await.unwrap().unwrap();
+
+    // The last report is the whole file, against the length the server sent
+    let length: u64 = FIXTURE.len() as u64;
+    assert_eq!(reports.lock().unwrap().last(), Some(&(length, length)));
+}
+
+#[tokio::test]
+async fn records_without_an_icao24_are_not_stored() {
+    let url: String = serve(FIXTURE.to_string()).await;
+    let (records, _) = download(&url).await;
+
+    let kept: Vec<bool> = records.iter().map(|record_info| record_info.record.filter()).collect();
+    assert_eq!(kept, [true, false, true]);
+
+    // The whole pipeline reads the three records and would store two
+    let config: Config = Config {
+        source: Source::from_url(url.clone()),
+        dry_run: true,
+        progress_mode: ProgressMode::Plain,
+        ..Config::default()
+    };
+
+    let summary: RunSummary = run_with_summary(config).await;
+
+    assert!(summary.succeeded());
+    assert_eq!((summary.records_read, summary.records_stored), (3, 2));
+
+    // With a limit of one the run stops after the first record, and the unread rest isn't an error,
+    // run here rather than in its own test as the counts come from the shared run metrics
+    let config: Config = Config {
+        source: Source::from_url(url),
+        dry_run: true,
+        limit: Some(1),
+        progress_mode: ProgressMode::Plain,
+        ..Config::default()
+    };
+
+    let summary: RunSummary = run_with_summary(config).await;
+
+    assert!(summary.succeeded());
+    assert_eq!((summary.records_read, summary.records_stored), (1, 1));
+}
+
+#[tokio::test]
+async fn the_stream_yields_the_records_as_they_would_be_stored() {
+    let url: String = serve(FIXTURE.to_string()).await;
+
+    let records: Vec<Result<Aircraft, DownloadError<Aircraft>>> = DownloadInfo::new().stream(&url).collect().await;
+
+    // The empty icao24 is filtered out and the addresses are uppercased by the map
+    let icao24s: Vec<String> = records.into_iter().map(|record| record.unwrap().icao24).collect();
+    assert_eq!(icao24s, ["4CA1FA", "A0B1C2"]);
+}
+
+#[tokio::test]
+async fn the_stream_ends_with_the_download_error() {
+    let mut lines: Vec<&str> = FIXTURE.split_inclusive("\r\n").collect();
+    lines.insert(2, "'c0ffee','2024-05-01 00:00:00'\r\n");
+
+    let url: String = serve(lines.concat()).await;
+    let mut records = pin!(DownloadInfo::<Aircraft>::new().stream(&url));
+
+    assert_eq!(records.next().await.unwrap().unwrap().icao24, "4CA1FA");
+    assert!(matches!(records.next().await, Some(Err(DownloadError::CsvError(_)))));
+    assert!(records.next().await.is_none());
+}
+
+#[tokio::test]
+async fn a_malformed_row_fails_the_download() {
+    // A row with only two of the fields, after the first record
+    let mut lines: Vec<&str> = FIXTURE.split_inclusive("\r\n").collect();
+    lines.insert(2, "'c0ffee','2024-05-01 00:00:00'\r\n");
+
+    let url: String = serve(lines.concat()).await;
+    let (records, result) = download(&url).await;
+
+    // The records before the bad row are sent, then the download stops with the CSV error
+    let icao24s: Vec<String> = records.iter().map(|record_info| field(record_info, "icao24")).collect();
+    assert_eq!(icao24s, ["4ca1fa"]);
+    assert!(matches!(result, Err(DownloadError::CsvError(_))));
+}